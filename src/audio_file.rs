@@ -1,12 +1,16 @@
 use std::fmt::Write;
 use std::fs;
+use std::fs::OpenOptions;
 use std::path::{Path, PathBuf};
 
 use regex::Regex;
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::model::AlbumInfo;
-use crate::tags;
-use crate::tags::{TagIO, Tags};
+use crate::journal::Journal;
+use crate::model::{AlbumInfo, Role, TrackInfo};
+use crate::tags::{Field, TagIO, Tags};
+use crate::{pool, tags};
 use anyhow::Result;
 use thiserror::Error;
 
@@ -30,6 +34,23 @@ pub fn find_audio_files(folder: &Path) -> Result<Vec<AudioFile>> {
     Ok(audio_files)
 }
 
+/// 指定されたファイルを音楽ファイルとして開く。
+pub fn open_audio_file(filepath: &Path) -> Result<AudioFile> {
+    match tags::tag_io_for(filepath) {
+        Some(tag_io) => Ok(AudioFile::new(filepath.to_path_buf(), tag_io)),
+        None => Err(FileAccessError::INSTANCE("対応していないファイル形式です"))?,
+    }
+}
+
+/// 指定されたフォルダに音楽ファイルがあるかを判定する。
+pub fn has_audio_files(folder: &Path) -> Result<bool> {
+    let has_audio_files = find_files(folder)?
+        .iter()
+        .any(|filepath| tags::tag_io_for(filepath.as_path()).is_some());
+
+    Ok(has_audio_files)
+}
+
 /// 指定されたフォルダのファイルを一覧にする。
 /// ファイル名の昇順でソートする。
 fn find_files(folder: &Path) -> Result<Vec<PathBuf>> {
@@ -54,47 +75,381 @@ fn find_files(folder: &Path) -> Result<Vec<PathBuf>> {
 }
 
 /// アルバム情報で音楽ファイルのタグ情報を更新する。
-pub fn update_by_album_info(audio_files: &mut Vec<AudioFile>, album: &AlbumInfo) -> Result<()> {
-    let mut audio_file_iter = audio_files.iter();
+///
+/// 書き込む前にすべての音楽ファイルとの対応付け、書き込み権限、値の形式を検証し、
+/// 問題がなければ変更前のタグ情報をジャーナルに記録してから書き込む。
+/// 書き込みに失敗した場合は、書き込み済みの音楽ファイルを変更前のタグ情報に戻す。
+///
+/// 音楽ファイルごとに変更前と変更後のタグ情報を返す。
+pub fn update_by_album_info(
+    audio_files: &[AudioFile],
+    album: &AlbumInfo,
+    journal: &mut Journal,
+) -> Result<Vec<(Tags, Tags)>> {
+    let new_tags = plan_by_album_info(audio_files, album)?;
+
+    validate_update(audio_files, &new_tags)?;
+
+    let original_tags = load_all_tags(audio_files)?;
+    for (audio_file, tags) in audio_files.iter().zip(original_tags.iter()) {
+        journal.record_tags(audio_file.filepath(), tags.clone());
+    }
+    journal.save()?;
+
+    let updates: Vec<(&AudioFile, &Tags)> = audio_files.iter().zip(new_tags.iter()).collect();
+    let results = pool::map(&updates, |(audio_file, tags)| audio_file.save_tags(tags));
+
+    // 並列に書き込むので、失敗したらすべてのファイルを戻す
+    let failure = results
+        .into_iter()
+        .zip(audio_files.iter())
+        .find_map(|(result, audio_file)| result.err().map(|e| (audio_file, e)));
+    if let Some((audio_file, e)) = failure {
+        let failed_files = rollback(audio_files, &original_tags);
+        let message = if failed_files.is_empty() {
+            format!(
+                "{} の書き込みに失敗したため、すべてのファイルを元に戻しました",
+                audio_file.filepath().display()
+            )
+        } else {
+            format!(
+                "{} の書き込みに失敗しました。次のファイルは元に戻せませんでした: {}",
+                audio_file.filepath().display(),
+                failed_files.join(", ")
+            )
+        };
+        return Err(e.context(message));
+    }
+
+    Ok(original_tags.into_iter().zip(new_tags).collect())
+}
+
+/// 音楽ファイルのタグ情報を並列に読み込む。
+///
+/// 結果は音楽ファイルと同じ順序で並び、失敗した場合は先頭に近いファイルのエラーを返す。
+pub fn load_all_tags(audio_files: &[AudioFile]) -> Result<Vec<Tags>> {
+    pool::map(audio_files, AudioFile::load_tags)
+        .into_iter()
+        .collect()
+}
+
+/// 音楽ファイルごとにタグ情報でリネームした場合のファイルパスを返す。
+///
+/// 番号を明示したトラックは総トラック数より大きい番号になることもあるので、
+/// ディスクごとに最大のトラック番号の桁数でも0埋めして、ファイル名の順とトラック番号の順をそろえる。
+pub fn renamed_filepaths(audio_files: &[AudioFile], all_tags: &[Tags]) -> Result<Vec<PathBuf>> {
+    audio_files
+        .iter()
+        .zip(all_tags.iter())
+        .map(|(audio_file, tags)| {
+            let max_track_number = all_tags
+                .iter()
+                .filter(|other| other.disc_number() == tags.disc_number())
+                .filter_map(Tags::track_number)
+                .max()
+                .unwrap_or(0);
+            audio_file.renamed_filepath(tags, max_track_number)
+        })
+        .collect()
+}
+
+/// リネーム先のファイルパスを検証する。
+///
+/// リネーム先にリネームしないファイルがある場合と、複数のファイルのリネーム先が同じ場合は、
+/// ファイルを上書きしてしまうので問題があったファイルをまとめてエラーにする。
+/// ほかの音楽ファイルがリネームで空けるパスは問題にしない。
+pub fn validate_renames(audio_files: &[AudioFile], new_filepaths: &[PathBuf]) -> Result<()> {
+    let moved_filepaths: Vec<&Path> = audio_files
+        .iter()
+        .zip(new_filepaths.iter())
+        .filter(|(audio_file, new_filepath)| audio_file.filepath() != new_filepath.as_path())
+        .map(|(audio_file, _)| audio_file.filepath())
+        .collect();
+
+    let mut problems = vec![];
+    for (index, (audio_file, new_filepath)) in audio_files.iter().zip(new_filepaths).enumerate() {
+        if audio_file.filepath() == new_filepath.as_path() {
+            continue;
+        }
+        let message = if new_filepaths[..index].contains(new_filepath)
+            || new_filepaths[index + 1..].contains(new_filepath)
+        {
+            "ほかの音楽ファイルと同じファイル名にリネームされます"
+        } else if new_filepath.exists() && !moved_filepaths.contains(&new_filepath.as_path()) {
+            "リネーム先のファイルが既にあります"
+        } else {
+            continue;
+        };
+        problems.push(FileProblem {
+            path: audio_file.filepath().display().to_string(),
+            code: "rename_conflict",
+            message: format!("{}: {}", message, new_filepath.display()),
+        });
+    }
+
+    if !problems.is_empty() {
+        Err(UpdateValidationError::INSTANCE(problems))?
+    }
+
+    Ok(())
+}
+
+/// 音楽ファイルにタグ情報が書き込めるかを検証する。
+/// 問題があったファイルをまとめてエラーにする。
+fn validate_update(audio_files: &[AudioFile], new_tags: &[Tags]) -> Result<()> {
+    let updates: Vec<(&AudioFile, &Tags)> = audio_files.iter().zip(new_tags.iter()).collect();
+
+    let problems: Vec<FileProblem> = pool::map(&updates, |(audio_file, tags)| {
+        let path = audio_file.filepath().display().to_string();
+        let mut problems = vec![];
+
+        if OpenOptions::new()
+            .write(true)
+            .open(audio_file.filepath())
+            .is_err()
+        {
+            problems.push(FileProblem {
+                path: path.clone(),
+                code: "not_writable",
+                message: "ファイルに書き込めません".to_string(),
+            });
+        }
+
+        if let Err(e) = audio_file.tag_io.validate(tags) {
+            problems.push(FileProblem {
+                path,
+                code: "invalid_tag_value",
+                message: e.to_string(),
+            });
+        }
+
+        problems
+    })
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if !problems.is_empty() {
+        Err(UpdateValidationError::INSTANCE(problems))?
+    }
+
+    Ok(())
+}
+
+/// 音楽ファイルを変更前のタグ情報に戻す。
+/// 戻せなかったファイルのパスを返す。
+fn rollback(audio_files: &[AudioFile], original_tags: &[Tags]) -> Vec<String> {
+    let rollbacks: Vec<(&AudioFile, &Tags)> =
+        audio_files.iter().zip(original_tags.iter()).collect();
+
+    pool::map(&rollbacks, |(audio_file, tags)| {
+        match audio_file.save_tags(tags) {
+            Ok(()) => None,
+            Err(_) => Some(audio_file.filepath().display().to_string()),
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// アルバム情報から音楽ファイルごとに設定するタグ情報を作成する。
+///
+/// ファイル名が指定されたトラックはそのファイルに、それ以外のトラックは残りのファイルに並び順で対応付ける。
+/// 戻り値は音楽ファイルと同じ順序で並ぶ。
+pub fn plan_by_album_info(audio_files: &[AudioFile], album: &AlbumInfo) -> Result<Vec<Tags>> {
+    let mut track_tags = Vec::with_capacity(audio_files.len());
+    let mut all_kept_fields = Vec::with_capacity(audio_files.len());
+    let mut tracks = Vec::with_capacity(audio_files.len());
 
     for (disc_index, disc) in album.discs().iter().enumerate() {
         let disc_number = disc_index + 1;
-        for (track_index, track) in disc.tracks().iter().enumerate() {
-            let track_number = track_index + 1;
-
-            let audio_file = match audio_file_iter.next() {
-                Some(audio_file) => audio_file,
-                None => Err(TitlesMismatchFilesError::INSTANCE)?,
-            };
+        let mut track_number = 0;
+        for track in disc.tracks() {
+            // トラック番号が明示されていなければ前のトラックの次の番号にする
+            track_number = track.number().unwrap_or(track_number + 1);
 
             let mut tags = Tags::new();
-            tags.update_album_info(&album);
-            tags.update_disc_info(disc_number, &disc);
-            tags.update_track_info(track_number, &track);
+            tags.update_album_info(album);
+            tags.update_disc_info(disc_number, disc);
+            tags.update_track_info(track_number, track);
+
+            let mut kept_fields = album.kept_fields().clone();
+            for &field in disc.kept_fields() {
+                if field != Field::Artists || track.artists().is_empty() {
+                    kept_fields.push(field);
+                }
+            }
+            kept_fields.extend(track.kept_fields());
 
-            audio_file.save_tags(&tags)?;
+            track_tags.push(tags);
+            all_kept_fields.push(kept_fields);
+            tracks.push((disc_number, track));
         }
     }
 
-    if let Some(_) = audio_file_iter.next() {
-        Err(TitlesMismatchFilesError::INSTANCE)?;
+    let order = match_tracks(audio_files, &tracks)?;
+    let mut track_tags: Vec<Option<Tags>> = track_tags.into_iter().map(Some).collect();
+    let mut new_tags: Vec<Tags> = order
+        .iter()
+        .map(|&index| track_tags[index].take().unwrap())
+        .collect();
+    let all_kept_fields: Vec<&Vec<Field>> =
+        order.iter().map(|&index| &all_kept_fields[index]).collect();
+
+    // 現在の値を残す項目があるファイルだけ読み込んで値を引き継ぐ
+    let merges: Vec<(&AudioFile, &&Vec<Field>)> = audio_files
+        .iter()
+        .zip(all_kept_fields.iter())
+        .filter(|(_, kept_fields)| !kept_fields.is_empty())
+        .collect();
+    let original_tags: Vec<Tags> = pool::map(&merges, |(audio_file, _)| audio_file.load_tags())
+        .into_iter()
+        .collect::<Result<_>>()?;
+    let mut original_tags = original_tags.into_iter();
+    for (tags, kept_fields) in new_tags.iter_mut().zip(all_kept_fields.iter()) {
+        if !kept_fields.is_empty() {
+            tags.keep_fields(&original_tags.next().unwrap(), kept_fields);
+        }
     }
 
-    Ok(())
+    Ok(new_tags)
+}
+
+/// 音楽ファイルごとに対応するトラックの位置を返す。
+///
+/// ファイル名が1つも指定されていなければ、並び順のまま対応付ける。
+/// ファイル名はmacOSのNFDのファイル名とも一致するように、どちらもNFCに正規化して比較する。
+fn match_tracks(audio_files: &[AudioFile], tracks: &[(usize, &TrackInfo)]) -> Result<Vec<usize>> {
+    if tracks.iter().all(|(_, track)| track.filename().is_none()) {
+        if tracks.len() != audio_files.len() {
+            Err(TitlesMismatchFilesError::INSTANCE(count_by_disc(
+                audio_files,
+                tracks,
+            )))?;
+        }
+        return Ok((0..tracks.len()).collect());
+    }
+
+    let audio_filenames: Vec<String> = audio_files
+        .iter()
+        .map(|audio_file| audio_file.filename().nfc().collect())
+        .collect();
+    let mut order: Vec<Option<usize>> = vec![None; audio_files.len()];
+    let mut unmatched_tracks = vec![];
+    for (index, (_, track)) in tracks.iter().enumerate() {
+        if let Some(filename) = track.filename() {
+            let filename: String = filename.nfc().collect();
+            match audio_filenames.iter().position(|other| *other == filename) {
+                Some(file_index) if order[file_index].is_none() => order[file_index] = Some(index),
+                // 同じファイル名の指定はtagsファイルの誤りにしているが、先の対応付けを上書きはしない
+                _ => unmatched_tracks.push(index),
+            }
+        }
+    }
+
+    // ファイル名が指定されていないトラックは残りのファイルに順に対応付ける
+    let free_files: Vec<usize> = (0..audio_files.len())
+        .filter(|&i| order[i].is_none())
+        .collect();
+    let mut free_files = free_files.into_iter();
+    for (index, (_, track)) in tracks.iter().enumerate() {
+        if track.filename().is_none() {
+            match free_files.next() {
+                Some(file_index) => order[file_index] = Some(index),
+                None => unmatched_tracks.push(index),
+            }
+        }
+    }
+
+    let unmatched_files: Vec<String> = audio_files
+        .iter()
+        .zip(order.iter())
+        .filter(|(_, index)| index.is_none())
+        .map(|(audio_file, _)| audio_file.filename().to_string())
+        .collect();
+    if !unmatched_tracks.is_empty() || !unmatched_files.is_empty() {
+        unmatched_tracks.sort();
+        let unmatched_tracks = unmatched_tracks
+            .into_iter()
+            .map(|index| {
+                let (disc_number, track) = tracks[index];
+                match track.filename() {
+                    Some(filename) => format!(
+                        "ディスク{}: {} @ {}",
+                        disc_number,
+                        track.title().unwrap_or("*"),
+                        filename
+                    ),
+                    None => format!("ディスク{}: {}", disc_number, track.title().unwrap_or("*")),
+                }
+            })
+            .collect();
+        Err(UnmatchedTracksError::INSTANCE(
+            unmatched_tracks,
+            unmatched_files,
+        ))?
+    }
+
+    Ok(order.into_iter().map(Option::unwrap).collect())
+}
+
+/// ディスクごとにtagsファイルのトラック数と音楽ファイル数を数える。
+///
+/// 音楽ファイルはタグのディスク番号で数え、タグが読めないかディスク番号がなければ番号なしとして数える。
+fn count_by_disc(audio_files: &[AudioFile], tracks: &[(usize, &TrackInfo)]) -> Vec<DiscCount> {
+    let file_disc_numbers: Vec<Option<usize>> = pool::map(audio_files, |audio_file| {
+        audio_file
+            .load_tags()
+            .ok()
+            .and_then(|tags| tags.disc_number())
+    });
+
+    let mut disc_numbers: Vec<Option<usize>> = tracks
+        .iter()
+        .map(|&(disc_number, _)| Some(disc_number))
+        .chain(file_disc_numbers.iter().copied())
+        .collect();
+    // ディスク番号順に並べ、番号なしは最後にする
+    disc_numbers.sort_by_key(|disc_number| (disc_number.is_none(), *disc_number));
+    disc_numbers.dedup();
+
+    disc_numbers
+        .into_iter()
+        .map(|disc_number| DiscCount {
+            disc_number,
+            tracks: tracks
+                .iter()
+                .filter(|&&(n, _)| Some(n) == disc_number)
+                .count(),
+            files: file_disc_numbers
+                .iter()
+                .filter(|&&n| n == disc_number)
+                .count(),
+        })
+        .collect()
 }
 
 /// 音楽ファイルのタグ情報を元にアルバム情報を作成する。
-pub fn to_album_info(audio_files: &Vec<AudioFile>) -> Result<AlbumInfo> {
-    let first_file = match audio_files.first() {
-        Some(audio_file) => audio_file,
+///
+/// タグ情報は音楽ファイルと同じ順序で並べて渡す。
+pub fn to_album_info(audio_files: &[AudioFile], all_tags: &[Tags]) -> Result<AlbumInfo> {
+    let tags = match all_tags.first() {
+        Some(tags) => tags,
         None => Err(NoAudioFileError::INSTANCE)?,
     };
 
-    let tags = first_file.load_tags()?;
     let album = tags.album().map(String::from);
     let album_artist = tags.album_artist().map(String::from);
     let release_date = tags.release_date().map(String::from);
     let mut album_info = AlbumInfo::new(album, album_artist, release_date);
+    album_info.set_genre(tags.genre().map(String::from));
+    album_info.set_label(tags.label().map(String::from));
+    album_info.set_catalog_number(tags.catalog_number().map(String::from));
+    album_info.set_barcode(tags.barcode().map(String::from));
+    album_info.set_compilation(tags.compilation());
+    album_info.set_comment(tags.comment().map(String::from));
+    album_info.set_original_release_date(tags.original_release_date().map(String::from));
 
     let art_work = match tags.art_work() {
         Some(art_work) => Some(art_work.clone()),
@@ -102,17 +457,43 @@ pub fn to_album_info(audio_files: &Vec<AudioFile>) -> Result<AlbumInfo> {
     };
     album_info.set_art_work(art_work);
 
-    // ディスク番号が設定されていても無視して1つのディスク情報に全トラック情報を格納する
-    let disc_info = album_info.new_disc();
+    // ディスク番号が変わるところでディスクを分ける
+    let mut disc_info = album_info.new_disc();
+    let mut disc_number = tags.disc_number();
+    let mut track_number = 0;
 
-    for audio_file in audio_files {
-        let tags = audio_file.load_tags()?;
+    for (audio_file, tags) in audio_files.iter().zip(all_tags.iter()) {
+        if tags.disc_number() != disc_number && !disc_info.tracks().is_empty() {
+            disc_info = album_info.new_disc();
+            track_number = 0;
+        }
+        disc_number = tags.disc_number();
+        if disc_info.tracks().is_empty() {
+            disc_info.set_title(tags.disc_title().map(String::from));
+        }
 
         let title = match tags.title() {
             Some(title) => title,
             None => audio_file.filepath.file_stem().unwrap().to_str().unwrap(),
         };
-        disc_info.new_track(Some(title.to_string()), tags.artists().clone());
+        let track_info = disc_info.new_track(Some(title.to_string()), tags.artists().clone());
+        for role in Role::ALL {
+            for name in tags.credits(role) {
+                track_info.add_credit(role, name.clone());
+            }
+        }
+
+        // 前のトラックの次の番号でなければトラック番号を明示する
+        if let Some(number) = tags.track_number() {
+            if number != track_number + 1 {
+                track_info.set_number(Some(number));
+            }
+            track_number = number;
+        } else {
+            track_number += 1;
+        }
+        track_info.set_position(tags.track_position().map(String::from));
+        track_info.set_filename(Some(audio_file.filename().to_string()));
     }
 
     Ok(album_info)
@@ -134,25 +515,55 @@ impl AudioFile {
         self.tag_io.save(self.filepath.as_path(), tags)
     }
 
-    /// タグ情報を元にファイルをリネームする。
-    pub fn rename(&mut self) -> Result<()> {
-        let tags = self.load_tags()?;
+    /// ファイルパスを返す。
+    pub fn filepath(&self) -> &Path {
+        self.filepath.as_path()
+    }
+
+    /// フォルダを除いたファイル名を返す。
+    pub fn filename(&self) -> &str {
+        self.filepath.file_name().unwrap().to_str().unwrap()
+    }
+
+    /// 指定されたファイルパスにリネームする。
+    pub fn rename(&mut self, new_filepath: PathBuf) -> Result<()> {
+        fs::rename(self.filepath.as_path(), new_filepath.as_path())?;
 
+        self.filepath = new_filepath;
+
+        Ok(())
+    }
+
+    /// 指定されたタグ情報でリネームした場合のファイルパスを返す。
+    ///
+    /// 現在の値を残す印で元のファイルにない値が残ることもあるので、
+    /// ファイル名に使う項目がなければ書き込み検証エラーにする。
+    ///
+    /// トラック番号は総トラック数と`max_track_number`の大きい方の桁数に0埋めする。
+    fn renamed_filepath(&self, tags: &Tags, max_track_number: usize) -> Result<PathBuf> {
         let mut filename = String::new();
 
-        if tags.number_of_discs().unwrap_or(1) > 1 {
-            let disc_number =
-                add_zero_paddings(tags.disc_number().unwrap(), tags.number_of_discs().unwrap());
+        if let Some(number_of_discs) = tags.number_of_discs().filter(|&n| n > 1) {
+            let disc_number = self.required_value(tags.disc_number(), Field::DiscNumber)?;
+            let disc_number = add_zero_paddings(disc_number, number_of_discs);
             write!(filename, "{}.", disc_number)?;
         };
 
-        let track_number = add_zero_paddings(
-            tags.track_number().unwrap(),
-            tags.number_of_tracks().unwrap(),
-        );
-        write!(filename, "{}.", track_number.as_str())?;
+        // レコードの面を含む位置があればトラック番号の代わりに使う
+        match tags.track_position() {
+            Some(track_position) => write!(filename, "{}.", track_position)?,
+            None => {
+                let number_of_tracks =
+                    self.required_value(tags.number_of_tracks(), Field::NumberOfTracks)?;
+                let track_number = add_zero_paddings(
+                    self.required_value(tags.track_number(), Field::TrackNumber)?,
+                    number_of_tracks.max(max_track_number),
+                );
+                write!(filename, "{}.", track_number.as_str())?;
+            }
+        }
 
-        filename.push_str(tags.title().unwrap());
+        filename.push_str(self.required_value(tags.title(), Field::Title)?);
 
         if let Some(extension) = self.filepath.extension() {
             let extension = extension.to_str().unwrap();
@@ -166,11 +577,20 @@ impl AudioFile {
         let mut new_filepath = self.filepath.clone();
         new_filepath.set_file_name(&*filename);
 
-        fs::rename(self.filepath.as_path(), new_filepath.as_path())?;
-
-        self.filepath = new_filepath;
+        Ok(new_filepath)
+    }
 
-        Ok(())
+    /// リネームに使う項目の値を返す。
+    /// 値がなければこのファイルの問題として書き込み検証エラーにする。
+    fn required_value<T>(&self, value: Option<T>, field: Field) -> Result<T> {
+        match value {
+            Some(value) => Ok(value),
+            None => Err(UpdateValidationError::INSTANCE(vec![FileProblem {
+                path: self.filepath().display().to_string(),
+                code: "missing_tag_value",
+                message: format!("ファイル名に使う{}がありません", field.name()),
+            }]))?,
+        }
     }
 }
 
@@ -188,8 +608,85 @@ fn add_zero_paddings(n: usize, max: usize) -> String {
 /// tagsファイルに書かれているタイトル数と対象となる音楽ファイル数が一致しない場合に発生する。
 #[derive(Debug, Error)]
 pub enum TitlesMismatchFilesError {
-    #[error("対象ファイル数とtagsのタイトル数が一致しません")]
-    INSTANCE,
+    #[error("対象ファイル数とtagsのタイトル数が一致しません{}", format_disc_counts(.0))]
+    INSTANCE(Vec<DiscCount>),
+}
+
+/// ディスクごとのtagsファイルのトラック数と音楽ファイル数
+#[derive(Debug, Serialize)]
+pub struct DiscCount {
+    disc_number: Option<usize>,
+    tracks: usize,
+    files: usize,
+}
+
+fn format_disc_counts(disc_counts: &[DiscCount]) -> String {
+    disc_counts
+        .iter()
+        .map(|disc_count| {
+            let disc = match disc_count.disc_number {
+                Some(disc_number) => format!("ディスク{}", disc_number),
+                None => "ディスク番号なし".to_string(),
+            };
+            let mark = if disc_count.tracks == disc_count.files {
+                ""
+            } else {
+                " (不一致)"
+            };
+            format!(
+                "\n{}: tagsファイル {}曲、音楽ファイル {}曲{}",
+                disc, disc_count.tracks, disc_count.files, mark
+            )
+        })
+        .collect()
+}
+
+/// トラック対応付けエラー
+///
+/// ファイル名が指定されたトラックの音楽ファイルがない場合や、
+/// 対応するトラックがない音楽ファイルが残った場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum UnmatchedTracksError {
+    #[error(
+        "tagsファイルのトラックと音楽ファイルが対応付けられません{}{}",
+        format_unmatched("対応するファイルがないトラック", .0),
+        format_unmatched("対応するトラックがないファイル", .1)
+    )]
+    INSTANCE(Vec<String>, Vec<String>),
+}
+
+fn format_unmatched(label: &str, names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("\n{}: {}", label, name))
+        .collect()
+}
+
+/// 書き込み検証エラー
+///
+/// インポート前の検証で書き込めない音楽ファイルや値が見つかった場合や、
+/// リネームでファイル名に使う値がない場合やリネーム先にファイルがある場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum UpdateValidationError {
+    #[error("書き込めない音楽ファイルがあるため処理を中止しました{}", format_problems(.0))]
+    INSTANCE(Vec<FileProblem>),
+}
+
+/// 音楽ファイルごとの問題
+#[derive(Debug, Serialize)]
+pub struct FileProblem {
+    path: String,
+    code: &'static str,
+    message: String,
+}
+
+fn format_problems(problems: &[FileProblem]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("\n{}: {}", problem.path, problem.message))
+        .collect()
 }
 
 /// ファイルアクセスエラー
@@ -207,3 +704,96 @@ pub enum NoAudioFileError {
     #[error("対象ファイルがありません")]
     INSTANCE,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track_tags(track_number: usize, title: &str) -> Tags {
+        let mut tags = Tags::new();
+        tags.set_number_of_tracks(Some(3));
+        tags.set_track_number(Some(track_number));
+        tags.set_title(Some(title.to_string()));
+        tags
+    }
+
+    fn filenames(filepaths: &[PathBuf]) -> Vec<&str> {
+        filepaths
+            .iter()
+            .map(|filepath| filepath.file_name().unwrap().to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn renamed_filepaths_pad_to_max_track_number() {
+        let audio_files: Vec<AudioFile> = ["a.flac", "b.flac", "c.flac"]
+            .iter()
+            .map(|filename| open_audio_file(Path::new(filename)).unwrap())
+            .collect();
+        let all_tags = [track_tags(1, "A"), track_tags(12, "B"), track_tags(2, "C")];
+
+        let new_filepaths = renamed_filepaths(&audio_files, &all_tags).unwrap();
+
+        assert_eq!(
+            filenames(&new_filepaths),
+            ["01.A.flac", "12.B.flac", "02.C.flac"]
+        );
+    }
+
+    #[test]
+    fn renamed_filepaths_pad_per_disc() {
+        let audio_files: Vec<AudioFile> = ["a.flac", "b.flac", "c.flac"]
+            .iter()
+            .map(|filename| open_audio_file(Path::new(filename)).unwrap())
+            .collect();
+        let mut all_tags = [track_tags(1, "A"), track_tags(10, "B"), track_tags(2, "C")];
+        for (tags, disc_number) in all_tags.iter_mut().zip([1, 1, 2]) {
+            tags.set_number_of_discs(Some(2));
+            tags.set_disc_number(Some(disc_number));
+        }
+
+        let new_filepaths = renamed_filepaths(&audio_files, &all_tags).unwrap();
+
+        assert_eq!(
+            filenames(&new_filepaths),
+            ["1.01.A.flac", "1.10.B.flac", "2.2.C.flac"]
+        );
+    }
+
+    #[test]
+    fn validate_renames_rejects_overwrites() {
+        let folder = tempfile::tempdir().unwrap();
+        for filename in ["a.flac", "b.flac", "c.flac"] {
+            fs::write(folder.path().join(filename), b"").unwrap();
+        }
+        let audio_files: Vec<AudioFile> = ["a.flac", "b.flac"]
+            .iter()
+            .map(|filename| open_audio_file(&folder.path().join(filename)).unwrap())
+            .collect();
+
+        // 入れ替えはリネームで空くので問題にしない
+        let swapped = [folder.path().join("b.flac"), folder.path().join("a.flac")];
+        assert!(validate_renames(&audio_files, &swapped).is_ok());
+
+        // リネームしないファイルは上書きしない
+        let existing = [folder.path().join("c.flac"), folder.path().join("b.flac")];
+        let error = validate_renames(&audio_files, &existing).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("リネーム先のファイルが既にあります"));
+
+        // 同じファイル名にリネームするファイルが複数あれば中止する
+        let duplicated = [folder.path().join("d.flac"), folder.path().join("d.flac")];
+        let error = validate_renames(&audio_files, &duplicated).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("ほかの音楽ファイルと同じファイル名にリネームされます"));
+
+        // リネームしないファイルのファイル名にリネームするのも上書きになる
+        let kept = [folder.path().join("b.flac"), folder.path().join("b.flac")];
+        let error = validate_renames(&audio_files, &kept).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("ほかの音楽ファイルと同じファイル名にリネームされます"));
+    }
+}
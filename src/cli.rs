@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+use std::thread;
+
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+use crate::output::Format;
+
+/// コマンドライン引数
+///
+/// サブコマンドを省略した場合は、アルバムフォルダごとにtagsファイルの有無で
+/// インポート＋リネームかエクスポートかを判断する。
+#[derive(Debug, Parser)]
+#[command(version, about = "音楽ファイルのタグとファイル名を一括編集する")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub update: UpdateArgs,
+}
+
+impl Cli {
+    /// 処理対象の指定を返す。比較処理にはない。
+    pub fn target(&self) -> Option<&TargetArgs> {
+        match &self.command {
+            Some(Command::Import(update)) | Some(Command::Rename(update)) => Some(&update.target),
+            Some(Command::Export(target))
+            | Some(Command::Status(target))
+            | Some(Command::Edit(target))
+            | Some(Command::Undo(target)) => Some(target),
+            Some(Command::Diff(_)) => None,
+            None => Some(&self.update.target),
+        }
+    }
+
+    /// アルバムフォルダとして指定された、存在しないサブコマンド名を返す。
+    ///
+    /// `utag import rename`のようにサブコマンドを続けて指定した場合や、
+    /// `utag --format json export`のようにオプションの後にサブコマンドを指定した場合に、
+    /// サブコマンド名がフォルダとして解釈されたものを見つける。
+    pub fn misplaced_subcommand(&self) -> Option<String> {
+        let command = Cli::command();
+        let names: Vec<&str> = command
+            .get_subcommands()
+            .flat_map(|subcommand| {
+                std::iter::once(subcommand.get_name()).chain(subcommand.get_all_aliases())
+            })
+            .collect();
+
+        self.target()?
+            .folders
+            .iter()
+            .filter(|folder| !folder.exists())
+            .filter_map(|folder| folder.to_str())
+            .find(|folder| names.contains(folder))
+            .map(str::to_string)
+    }
+}
+
+/// サブコマンド
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// tagsファイルの内容を音楽ファイルのタグに設定する
+    #[command(visible_alias = "i")]
+    Import(UpdateArgs),
+
+    /// 音楽ファイルのタグからtagsファイルとアートワークを出力する
+    #[command(visible_alias = "e")]
+    Export(TargetArgs),
+
+    /// タグを元に音楽ファイルをリネームする
+    #[command(visible_alias = "r")]
+    Rename(UpdateArgs),
+
+    /// tagsファイルの内容と音楽ファイルのタグとファイル名が一致しているかを確認する
+    #[command(visible_alias = "verify")]
+    Status(TargetArgs),
+
+    /// エクスポートしたtagsファイルをエディタで編集してインポートする
+    Edit(TargetArgs),
+
+    /// 最後に実行したインポートとリネームを取り消す
+    #[command(visible_alias = "u")]
+    Undo(TargetArgs),
+
+    /// 2つの音楽ファイルまたはアルバムフォルダのタグを比較する
+    Diff(DiffArgs),
+}
+
+/// 処理対象の指定
+#[derive(Debug, Args)]
+pub struct TargetArgs {
+    /// 処理対象のアルバムフォルダ
+    #[arg(value_name = "FOLDER", default_value = ".")]
+    pub folders: Vec<PathBuf>,
+
+    /// tagsファイルのパス(相対パスはアルバムフォルダからのパスとみなす)
+    #[arg(long = "tags", value_name = "PATH")]
+    pub tags_filepath: Option<PathBuf>,
+
+    /// 指定したフォルダ配下を再帰的に探して見つかったアルバムフォルダをすべて処理する
+    #[arg(short = 'R', long)]
+    pub recursive: bool,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+/// 処理全体に関わる指定
+#[derive(Debug, Args)]
+pub struct CommonArgs {
+    /// タグを並列に読み書きする数(省略時はCPU数)
+    #[arg(short = 'j', long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// 結果の出力形式
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+}
+
+impl CommonArgs {
+    /// タグを並列に読み書きする数を返す。
+    pub fn jobs(&self) -> usize {
+        match self.jobs {
+            Some(jobs) => jobs,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+/// タグやファイル名を変更する処理の指定
+#[derive(Debug, Args)]
+pub struct UpdateArgs {
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    /// 変更内容を表示するだけで実際には変更しない
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+/// 比較する対象の指定
+///
+/// 音楽ファイル同士かアルバムフォルダ同士を指定する。
+/// アルバムフォルダ同士の場合はファイル名順に対応付けて比較する。
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// 比較元の音楽ファイルまたはアルバムフォルダ
+    #[arg(value_name = "LEFT")]
+    pub left: PathBuf,
+
+    /// 比較先の音楽ファイルまたはアルバムフォルダ
+    #[arg(value_name = "RIGHT")]
+    pub right: PathBuf,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
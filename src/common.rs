@@ -1,9 +1,67 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use once_cell::sync::Lazy;
 
 /// tagsファイル名
 pub static TAGS_FILENAME: Lazy<&Path> = Lazy::new(|| Path::new("tags"));
 
-/// 対象フォルダパス
-pub static TARGET_FOLDER: Lazy<&Path> = Lazy::new(|| Path::new("."));
+/// 構造化形式のtagsファイル名
+static STRUCTURED_TAGS_FILENAMES: Lazy<Vec<&Path>> = Lazy::new(|| {
+    ["tags.json", "tags.toml", "tags.yaml", "tags.yml"]
+        .into_iter()
+        .map(Path::new)
+        .collect()
+});
+
+/// ジャーナルファイル名
+pub static JOURNAL_FILENAME: Lazy<&Path> = Lazy::new(|| Path::new(".utag_journal"));
+
+/// 処理対象のアルバムフォルダ
+#[derive(Debug)]
+pub struct AlbumFolder {
+    folder: PathBuf,
+    tags_filepath: PathBuf,
+}
+
+impl AlbumFolder {
+    /// アルバムフォルダを作成する。
+    ///
+    /// tagsファイルのパスが指定されなければフォルダ直下のtagsファイルとする。
+    /// tagsファイルがなく、構造化形式のtagsファイル(tags.json、tags.toml、tags.yaml、tags.yml)が
+    /// あればそのファイルとする。
+    /// CUEシートがあるだけでインポートにならないように、CUEシートは指定された場合だけ使う。
+    /// 相対パスが指定された場合はフォルダからのパスとみなす。
+    pub fn new(folder: PathBuf, tags_filepath: Option<&Path>) -> AlbumFolder {
+        let tags_filepath = match tags_filepath {
+            Some(tags_filepath) => folder.join(tags_filepath),
+            None => {
+                let default_filepath = folder.join(*TAGS_FILENAME);
+                if default_filepath.exists() {
+                    default_filepath
+                } else {
+                    STRUCTURED_TAGS_FILENAMES
+                        .iter()
+                        .map(|filename| folder.join(filename))
+                        .find(|tags_filepath| tags_filepath.exists())
+                        .unwrap_or(default_filepath)
+                }
+            }
+        };
+        AlbumFolder {
+            folder,
+            tags_filepath,
+        }
+    }
+
+    pub fn folder(&self) -> &Path {
+        self.folder.as_path()
+    }
+
+    pub fn tags_filepath(&self) -> &Path {
+        self.tags_filepath.as_path()
+    }
+
+    pub fn journal_filepath(&self) -> PathBuf {
+        self.folder.join(*JOURNAL_FILENAME)
+    }
+}
@@ -0,0 +1,48 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::Result;
+use thiserror::Error;
+
+/// エディタが指定されていない場合に使うエディタ
+const DEFAULT_EDITOR: &str = if cfg!(windows) { "notepad" } else { "vi" };
+
+/// 環境変数VISUALまたはEDITORのエディタでファイルを開き、エディタが終了するまで待つ。
+pub fn edit(filepath: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+
+    // "code --wait"のように引数付きで指定されることがある
+    let mut words = editor.split_whitespace();
+    let program = match words.next() {
+        Some(program) => program,
+        None => DEFAULT_EDITOR,
+    };
+
+    let status = match Command::new(program).args(words).arg(filepath).status() {
+        Ok(status) => status,
+        Err(_) => Err(EditorError::INSTANCE(format!(
+            "エディタを起動できませんでした: {}",
+            editor
+        )))?,
+    };
+
+    if !status.success() {
+        Err(EditorError::INSTANCE(format!(
+            "エディタが異常終了しました: {}",
+            status
+        )))?
+    }
+
+    Ok(())
+}
+
+/// エディタエラー
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum EditorError {
+    #[error("{0}")]
+    INSTANCE(String),
+}
@@ -1,73 +1,847 @@
 use anyhow::Result;
-use std::env;
-use std::path::Path;
+use clap::Parser;
+use serde_json::{json, Value};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
-use crate::audio_file::AudioFile;
-use crate::common::{TAGS_FILENAME, TARGET_FOLDER};
-use crate::{audio_file, tags_file};
+use crate::audio_file::{AudioFile, TitlesMismatchFilesError, UnmatchedTracksError};
+use crate::cli::{Cli, Command, TargetArgs};
+use crate::common::{AlbumFolder, TAGS_FILENAME};
+use crate::journal::{Change, Journal};
+use crate::output::{path_json, say, TagsReport};
+use crate::tags::{Field, Tags};
+use crate::tags_file::ParseTagsError;
+use crate::{audio_file, editor, output, pool, tags_file};
+
+/// エディタで開き直すときにtagsファイルの先頭に書くエラー内容の接頭辞
+const EDIT_ERROR_PREFIX: &str = "# utag: ";
+
+/// ファイル名を入れ替えるリネームで一時的に付けるファイル名の接尾辞
+const RENAME_TEMP_SUFFIX: &str = ".utag_rename";
 
 /// 実行する処理を判断して順次実行する。
 pub fn execute() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-
-    // サブコマンドが指定されていない場合(1つ目の引数はプログラム名)
-    if args.len() == 1 {
-        // tagsファイルの有無でインポート/エクスポートのどちらかを実行する
-        if TAGS_FILENAME.exists() {
-            import_flow()?;
-            rename_flow()?;
+    let cli = Cli::parse();
+
+    let misplaced_subcommand = cli.misplaced_subcommand();
+
+    let common = match &cli.command {
+        Some(Command::Import(update)) | Some(Command::Rename(update)) => &update.target.common,
+        Some(Command::Export(target))
+        | Some(Command::Status(target))
+        | Some(Command::Edit(target))
+        | Some(Command::Undo(target)) => &target.common,
+        Some(Command::Diff(diff)) => &diff.common,
+        None => &cli.update.target.common,
+    };
+    pool::set_jobs(common.jobs());
+    output::set_format(common.format);
+
+    // 比較処理はアルバムフォルダ単位ではないので、結果もエラーも自身で出力する
+    let reports_errors = matches!(cli.command, Some(Command::Diff(_)));
+
+    let result = match misplaced_subcommand {
+        Some(subcommand) => Err(SubcommandPositionError::INSTANCE(subcommand).into()),
+        None => dispatch(cli),
+    };
+
+    // JSON形式では、アルバムフォルダごとの結果に含まれないエラーもJSONで出力する
+    if output::is_json() && !reports_errors {
+        if let Err(e) = &result {
+            if !e.is::<AlbumsFailedError>() {
+                output::print_json(&json!({
+                    "albums": [],
+                    "error": output::error_json(e),
+                }));
+            }
+        }
+    }
+
+    result
+}
+
+/// サブコマンドに対応する処理を実行する。
+fn dispatch(cli: Cli) -> Result<()> {
+    match cli.command {
+        Some(Command::Import(update)) => {
+            let album_folders = album_folders(&update.target, |album_folder| {
+                Ok(album_folder.tags_filepath().exists())
+            })?;
+            run_each(&album_folders, |album_folder| {
+                let mut journal = Journal::begin(album_folder.journal_filepath())?;
+                import_flow(album_folder, update.dry_run, false, &mut journal)
+            })?;
+        }
+        Some(Command::Export(target)) => {
+            let album_folders = album_folders(&target, |album_folder| {
+                audio_file::has_audio_files(album_folder.folder())
+            })?;
+            run_each(&album_folders, export_flow)?;
+        }
+        Some(Command::Rename(update)) => {
+            let album_folders = album_folders(&update.target, |album_folder| {
+                audio_file::has_audio_files(album_folder.folder())
+            })?;
+            run_each(&album_folders, |album_folder| {
+                let mut journal = Journal::begin(album_folder.journal_filepath())?;
+                rename_flow(album_folder, update.dry_run, &mut journal)
+            })?;
+        }
+        Some(Command::Status(target)) => {
+            let album_folders = album_folders(&target, |album_folder| {
+                Ok(album_folder.tags_filepath().exists())
+            })?;
+            run_each(&album_folders, status_flow)?;
+        }
+        Some(Command::Edit(target)) => {
+            let album_folders = album_folders(&target, |album_folder| {
+                audio_file::has_audio_files(album_folder.folder())
+            })?;
+            run_each(&album_folders, edit_flow)?;
+        }
+        Some(Command::Undo(target)) => {
+            let album_folders = album_folders(&target, |album_folder| {
+                Ok(album_folder.journal_filepath().exists())
+            })?;
+            run_each(&album_folders, undo_flow)?;
+        }
+        Some(Command::Diff(diff)) => {
+            let result = diff_flow(&diff.left, &diff.right);
+            if output::is_json() {
+                match &result {
+                    Ok(value) => output::print_json(&json!({ "ok": true, "result": value })),
+                    Err(e) => output::print_json(&json!({
+                        "ok": false,
+                        "error": output::error_json(e),
+                    })),
+                }
+            }
+            result?;
+        }
+        // サブコマンドが指定されていない場合
+        None => {
+            let update = cli.update;
+            let album_folders = album_folders(&update.target, |album_folder| {
+                Ok(album_folder.tags_filepath().exists()
+                    || audio_file::has_audio_files(album_folder.folder())?)
+            })?;
+            run_each(&album_folders, |album_folder| {
+                // tagsファイルの有無でインポート/エクスポートのどちらかを実行する
+                if album_folder.tags_filepath().exists() {
+                    // インポートとリネームはまとめて1回の実行として取り消せるようにする
+                    let mut journal = Journal::begin(album_folder.journal_filepath())?;
+                    let import = import_flow(album_folder, update.dry_run, true, &mut journal)?;
+                    // 予行演習ではインポート処理でリネーム後のファイル名まで表示している
+                    let rename = if update.dry_run {
+                        Value::Null
+                    } else {
+                        rename_flow(album_folder, false, &mut journal)?
+                    };
+                    Ok(json!({ "import": import, "rename": rename }))
+                } else {
+                    Ok(json!({ "export": export_flow(album_folder)? }))
+                }
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 引数で指定された処理対象のアルバムフォルダを一覧にする。
+///
+/// 再帰的に探す場合は、配下のフォルダのうちアルバムフォルダと判定されたものだけを対象にする。
+/// 読み込めないフォルダや判定に失敗したフォルダは、処理の失敗として報告するために対象に含める。
+fn album_folders(
+    target: &TargetArgs,
+    is_album_folder: impl Fn(&AlbumFolder) -> Result<bool>,
+) -> Result<Vec<AlbumFolder>> {
+    let mut album_folders = vec![];
+
+    for folder in target.folders.iter() {
+        if target.recursive {
+            for folder in find_folders(folder) {
+                let album_folder = AlbumFolder::new(folder, target.tags_filepath.as_deref());
+                if album_folder.folder().read_dir().is_err()
+                    || is_album_folder(&album_folder).unwrap_or(true)
+                {
+                    album_folders.push(album_folder);
+                }
+            }
         } else {
-            export_flow()?;
+            let album_folder = AlbumFolder::new(folder.clone(), target.tags_filepath.as_deref());
+            album_folders.push(album_folder);
+        }
+    }
+
+    Ok(album_folders)
+}
+
+/// 指定されたフォルダとその配下のフォルダを再帰的に一覧にする。
+/// フォルダ名の昇順でソートする。
+///
+/// シンボリックリンクが循環していると終わらなくなるので、シンボリックリンクのフォルダはたどらない。
+/// 読み込めないフォルダは配下をたどらずに一覧に含め、ほかのフォルダは探し続ける。
+fn find_folders(root: &Path) -> Vec<PathBuf> {
+    let mut folders = vec![root.to_path_buf()];
+
+    if let Ok(sub_folders) = find_sub_folders(root) {
+        for sub_folder in sub_folders {
+            folders.append(&mut find_folders(&sub_folder));
+        }
+    }
+
+    folders
+}
+
+/// 指定されたフォルダの直下のフォルダをフォルダ名の昇順で一覧にする。
+fn find_sub_folders(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut sub_folders = vec![];
+    for entry in folder.read_dir()? {
+        let entry = entry?;
+        // DirEntry::file_typeはシンボリックリンクをたどらない
+        if entry.file_type()?.is_dir() {
+            sub_folders.push(entry.path());
+        }
+    }
+    sub_folders.sort();
+
+    Ok(sub_folders)
+}
+
+/// アルバムフォルダが読み込めるかを確認してから処理を実行する。
+fn run_in_folder(
+    album_folder: &AlbumFolder,
+    flow: &impl Fn(&AlbumFolder) -> Result<Value>,
+) -> Result<Value> {
+    if let Err(e) = album_folder.folder().read_dir() {
+        return Err(anyhow::Error::from(e).context(format!(
+            "フォルダが読み込めません: {}",
+            album_folder.folder().display()
+        )));
+    }
+
+    flow(album_folder)
+}
+
+/// アルバムフォルダごとに処理を実行する。
+///
+/// 文章形式では、対象が1つならエラーをそのまま返す。
+/// 複数なら失敗しても残りのアルバムフォルダの処理を続け、最後に結果をまとめて表示する。
+///
+/// JSON形式では、対象の数に関係なくすべて処理してから結果をまとめて出力する。
+fn run_each(
+    album_folders: &[AlbumFolder],
+    flow: impl Fn(&AlbumFolder) -> Result<Value>,
+) -> Result<()> {
+    match album_folders.len() {
+        0 => Err(NoTargetError::INSTANCE)?,
+        1 if !output::is_json() => return run_in_folder(&album_folders[0], &flow).map(|_| ()),
+        _ => {}
+    }
+
+    let mut results = vec![];
+    for album_folder in album_folders {
+        let result = run_in_folder(album_folder, &flow);
+        if let Err(e) = &result {
+            say!(
+                "{} の処理に失敗しました: {:#}",
+                album_folder.folder().display(),
+                e
+            );
         }
+        results.push((album_folder, result));
+    }
+
+    let number_of_failures = results.iter().filter(|(_, r)| r.is_err()).count();
+
+    if output::is_json() {
+        let albums: Vec<Value> = results
+            .iter()
+            .map(|(album_folder, result)| match result {
+                Ok(value) => json!({
+                    "folder": path_json(album_folder.folder()),
+                    "ok": true,
+                    "result": value,
+                }),
+                Err(e) => json!({
+                    "folder": path_json(album_folder.folder()),
+                    "ok": false,
+                    "error": output::error_json(e),
+                }),
+            })
+            .collect();
+        output::print_json(&json!({ "albums": albums }));
     } else {
-        for subcommand in args.iter().skip(1) {
-            match subcommand.as_str() {
-                "import" => import_flow()?,
-                "export" => export_flow()?,
-                "rename" => rename_flow()?,
-                _ => Err(NoSuchSubcommandError::INSTANCE(subcommand.to_owned()))?,
+        println!();
+        println!(
+            "処理結果: 成功 {}件 / 失敗 {}件",
+            results.len() - number_of_failures,
+            number_of_failures
+        );
+        for (album_folder, result) in results.iter() {
+            match result {
+                Ok(_) => println!("  成功: {}", album_folder.folder().display()),
+                Err(e) => println!("  失敗: {}: {:#}", album_folder.folder().display(), e),
             }
         }
     }
 
+    if number_of_failures > 0 {
+        Err(AlbumsFailedError::INSTANCE(number_of_failures))?
+    }
+
     Ok(())
 }
 
 /// インポート処理を実行する。
-fn import_flow() -> Result<()> {
-    println!("インポート処理を開始します。");
+///
+/// 変更前のタグ情報はジャーナルに記録される。
+///
+/// 予行演習ではタグを変更せずに、ファイルごとに現在のタグと設定するタグを表示する。
+/// 続けてリネームする場合はリネーム後のファイル名も表示する。
+fn import_flow(
+    album_folder: &AlbumFolder,
+    dry_run: bool,
+    with_rename: bool,
+    journal: &mut Journal,
+) -> Result<Value> {
+    say!(
+        "{} のインポート処理を開始します。",
+        album_folder.folder().display()
+    );
 
-    let album_info = tags_file::load_tags_file(&TAGS_FILENAME)?;
+    let album_info =
+        tags_file::load_tags_file(album_folder.tags_filepath(), album_folder.folder())?;
 
-    let mut audio_files = require_audio_files(&TARGET_FOLDER)?;
+    let audio_files = require_audio_files(album_folder.folder())?;
 
-    audio_file::update_by_album_info(&mut audio_files, &album_info)?;
+    let mut files = vec![];
 
-    println!("インポート処理を完了しました。");
+    if dry_run {
+        let new_tags = audio_file::plan_by_album_info(&audio_files, &album_info)?;
+        let current_tags = audio_file::load_all_tags(&audio_files)?;
+        let new_filepaths = if with_rename {
+            let new_filepaths = audio_file::renamed_filepaths(&audio_files, &new_tags)?;
+            new_filepaths.into_iter().map(Some).collect()
+        } else {
+            vec![None; audio_files.len()]
+        };
+        for (((audio_file, current_tags), new_tags), new_filepath) in audio_files
+            .iter()
+            .zip(current_tags.iter())
+            .zip(new_tags.iter())
+            .zip(new_filepaths.iter())
+        {
+            print_import_plan(audio_file, current_tags, new_tags, new_filepath.as_deref());
+            files.push(import_file_json(
+                audio_file,
+                current_tags,
+                new_tags,
+                new_filepath.as_deref(),
+            ));
+        }
 
-    Ok(())
+        say!("予行演習のためタグを変更しませんでした。");
+    } else {
+        let updates = audio_file::update_by_album_info(&audio_files, &album_info, journal)?;
+        for (audio_file, (old_tags, new_tags)) in audio_files.iter().zip(updates.iter()) {
+            files.push(import_file_json(audio_file, old_tags, new_tags, None));
+        }
+
+        say!("インポート処理を完了しました。");
+    }
+
+    Ok(json!({ "dry_run": dry_run, "files": files }))
+}
+
+/// インポートした音楽ファイルの結果をJSONの値にする。
+fn import_file_json(
+    audio_file: &AudioFile,
+    old_tags: &Tags,
+    new_tags: &Tags,
+    new_filepath: Option<&Path>,
+) -> Value {
+    let mut value = json!({
+        "path": path_json(audio_file.filepath()),
+        "old": TagsReport::from(old_tags),
+        "new": TagsReport::from(new_tags),
+        "changes": old_tags.differences(new_tags),
+    });
+    if let Some(new_filepath) = new_filepath {
+        value["new_path"] = path_json(new_filepath);
+    }
+    value
+}
+
+/// 音楽ファイルの現在のタグと設定するタグを並べて表示する。
+/// 値が変わる項目には行頭に*を付ける。
+fn print_import_plan(
+    audio_file: &AudioFile,
+    current_tags: &Tags,
+    new_tags: &Tags,
+    new_filepath: Option<&Path>,
+) {
+    say!("{}", filename(audio_file.filepath()));
+
+    let differences = current_tags.differences(new_tags);
+    for ((field, current), (_, new)) in current_tags.fields().into_iter().zip(new_tags.fields()) {
+        let mark = if differences.contains(&field) {
+            '*'
+        } else {
+            ' '
+        };
+        say!(
+            "{} {}: {} -> {}",
+            mark,
+            field.name(),
+            current.as_deref().unwrap_or("(なし)"),
+            new.as_deref().unwrap_or("(なし)")
+        );
+    }
+
+    if let Some(new_filepath) = new_filepath {
+        let mark = if audio_file.filepath() == new_filepath {
+            ' '
+        } else {
+            '*'
+        };
+        say!(
+            "{} ファイル名: {} -> {}",
+            mark,
+            filename(audio_file.filepath()),
+            filename(new_filepath)
+        );
+    }
 }
 
 /// リネーム処理を実行する。
-fn rename_flow() -> Result<()> {
-    println!("リネーム処理を開始します。");
+///
+/// 実行したリネームはジャーナルに記録する。
+///
+/// 予行演習ではリネームせずに、ファイルごとに現在のファイル名とリネーム後のファイル名を表示する。
+fn rename_flow(album_folder: &AlbumFolder, dry_run: bool, journal: &mut Journal) -> Result<Value> {
+    say!(
+        "{} のリネーム処理を開始します。",
+        album_folder.folder().display()
+    );
+
+    let mut audio_files = require_audio_files(album_folder.folder())?;
+
+    let mut files = vec![];
+
+    if dry_run {
+        let all_tags = audio_file::load_all_tags(&audio_files)?;
+        let new_filepaths = audio_file::renamed_filepaths(&audio_files, &all_tags)?;
+        for (audio_file, new_filepath) in audio_files.iter().zip(new_filepaths.iter()) {
+            say!(
+                "{} -> {}",
+                filename(audio_file.filepath()),
+                filename(new_filepath)
+            );
+            files.push(json!({
+                "path": path_json(audio_file.filepath()),
+                "new_path": path_json(new_filepath),
+            }));
+        }
 
-    let mut audio_files = require_audio_files(&TARGET_FOLDER)?;
+        say!("予行演習のためリネームしませんでした。");
+    } else {
+        let old_filenames: Vec<String> = audio_files
+            .iter()
+            .map(|audio_file| audio_file.filename().to_string())
+            .collect();
+        let result = rename_all(&mut audio_files, journal, &mut files);
+        // 途中で失敗しても、それまでに実行したリネームは取り消せるようにする
+        journal.save()?;
+        // tagsファイルで指定されたファイル名もリネーム後のファイル名に合わせる
+        let renames: Vec<(String, String)> = old_filenames
+            .into_iter()
+            .zip(audio_files.iter())
+            .filter(|(old_filename, audio_file)| old_filename != audio_file.filename())
+            .map(|(old_filename, audio_file)| (old_filename, audio_file.filename().to_string()))
+            .collect();
+        tags_file::rename_anchors(album_folder.tags_filepath(), &renames)?;
+        result?;
 
-    for audio_file in audio_files.iter_mut() {
-        audio_file.rename()?;
+        say!("リネーム処理を完了しました。");
     }
 
-    println!("リネーム処理を完了しました。");
+    Ok(json!({ "dry_run": dry_run, "files": files }))
+}
+
+/// 照合処理を実行する。
+///
+/// ファイルごとにタグとファイル名がtagsファイルの内容でインポートとリネームをした状態と
+/// 一致しているかを表示する。ファイルは変更しない。
+/// 一致しないファイルがあればエラーにする。
+fn status_flow(album_folder: &AlbumFolder) -> Result<Value> {
+    say!(
+        "{} の照合処理を開始します。",
+        album_folder.folder().display()
+    );
+
+    let album_info =
+        tags_file::load_tags_file(album_folder.tags_filepath(), album_folder.folder())?;
+
+    let audio_files = require_audio_files(album_folder.folder())?;
+
+    let expected_tags = audio_file::plan_by_album_info(&audio_files, &album_info)?;
+    let current_tags = audio_file::load_all_tags(&audio_files)?;
+    let expected_filepaths = audio_file::renamed_filepaths(&audio_files, &expected_tags)?;
+
+    let mut files = vec![];
+    let mut number_of_mismatches = 0;
+    for (((audio_file, current_tags), expected_tags), expected_filepath) in audio_files
+        .iter()
+        .zip(current_tags.iter())
+        .zip(expected_tags.iter())
+        .zip(expected_filepaths.iter())
+    {
+        let differences = current_tags.differences(expected_tags);
+        let filename_matches = expected_filepath == audio_file.filepath();
+
+        let mut names: Vec<&str> = differences.iter().map(|field| field.name()).collect();
+        if !filename_matches {
+            names.push("ファイル名");
+        }
+
+        if names.is_empty() {
+            say!("  一致: {}", filename(audio_file.filepath()));
+        } else {
+            number_of_mismatches += 1;
+            say!(
+                "不一致: {}: {}",
+                filename(audio_file.filepath()),
+                names.join(", ")
+            );
+        }
+
+        files.push(json!({
+            "path": path_json(audio_file.filepath()),
+            "in_sync": names.is_empty(),
+            "differences": differences,
+            "filename_matches": filename_matches,
+            "expected_path": path_json(expected_filepath),
+        }));
+    }
+
+    // JSON形式では不一致もエラーとして出力されるので、ファイルごとの結果はエラーの詳細に含める
+    if number_of_mismatches > 0 {
+        Err(OutOfSyncError::INSTANCE(
+            number_of_mismatches,
+            files.clone(),
+        ))?
+    }
+
+    say!("照合処理を完了しました。すべて一致しています。");
+
+    Ok(json!({ "in_sync": true, "files": files }))
+}
+
+/// 比較処理を実行する。
+///
+/// 音楽ファイル同士ならその2つを、アルバムフォルダ同士ならファイル名順に対応付けた
+/// 音楽ファイルを比較して、値が異なる項目を表示する。
+/// ファイル形式が異なっていても、読み込んだタグ情報の項目ごとに比較する。
+fn diff_flow(left: &Path, right: &Path) -> Result<Value> {
+    let (left_files, right_files) = if left.is_file() && right.is_file() {
+        (
+            vec![audio_file::open_audio_file(left)?],
+            vec![audio_file::open_audio_file(right)?],
+        )
+    } else if left.is_dir() && right.is_dir() {
+        (require_audio_files(left)?, require_audio_files(right)?)
+    } else {
+        Err(DiffTargetError::INSTANCE)?
+    };
+
+    say!(
+        "{} と {} の比較処理を開始します。",
+        left.display(),
+        right.display()
+    );
+
+    let left_tags = audio_file::load_all_tags(&left_files)?;
+    let right_tags = audio_file::load_all_tags(&right_files)?;
+
+    let mut files = vec![];
+    let mut number_of_differences = 0;
+    for ((left_file, left_tags), (right_file, right_tags)) in left_files
+        .iter()
+        .zip(left_tags.iter())
+        .zip(right_files.iter().zip(right_tags.iter()))
+    {
+        let different_fields = left_tags.differences(right_tags);
+        let differences: Vec<(Field, Option<String>, Option<String>)> = left_tags
+            .fields()
+            .into_iter()
+            .zip(right_tags.fields())
+            .filter(|((field, _), _)| different_fields.contains(field))
+            .map(|((field, left_value), (_, right_value))| (field, left_value, right_value))
+            .collect();
+
+        if differences.is_empty() {
+            say!(
+                "  一致: {} <-> {}",
+                filename(left_file.filepath()),
+                filename(right_file.filepath())
+            );
+        } else {
+            number_of_differences += 1;
+            say!(
+                "不一致: {} <-> {}",
+                filename(left_file.filepath()),
+                filename(right_file.filepath())
+            );
+            for (field, left_value, right_value) in differences.iter() {
+                say!(
+                    "    {}: {} | {}",
+                    field.name(),
+                    left_value.as_deref().unwrap_or("(なし)"),
+                    right_value.as_deref().unwrap_or("(なし)")
+                );
+            }
+        }
+
+        let differences: Vec<Value> = differences
+            .into_iter()
+            .map(|(field, left_value, right_value)| {
+                json!({ "field": field, "left": left_value, "right": right_value })
+            })
+            .collect();
+
+        files.push(json!({
+            "left": path_json(left_file.filepath()),
+            "right": path_json(right_file.filepath()),
+            "differences": differences,
+        }));
+    }
+
+    // 対応するファイルがない音楽ファイルも不一致とする
+    for left_file in left_files.iter().skip(right_files.len()) {
+        number_of_differences += 1;
+        say!(
+            "対応するファイルがありません: {}",
+            left_file.filepath().display()
+        );
+        files.push(json!({ "left": path_json(left_file.filepath()), "right": null }));
+    }
+    for right_file in right_files.iter().skip(left_files.len()) {
+        number_of_differences += 1;
+        say!(
+            "対応するファイルがありません: {}",
+            right_file.filepath().display()
+        );
+        files.push(json!({ "left": null, "right": path_json(right_file.filepath()) }));
+    }
+
+    if number_of_differences > 0 {
+        Err(TagsDifferError::INSTANCE(
+            number_of_differences,
+            files.clone(),
+        ))?
+    }
+
+    say!("比較処理を完了しました。すべて一致しています。");
+
+    Ok(json!({ "in_sync": true, "files": files }))
+}
+
+/// 編集処理を実行する。
+///
+/// 音楽ファイルのタグからtagsファイルを一時ファイルに出力してエディタで開き、
+/// 保存された内容をインポートする。
+/// tagsファイルに誤りがあれば、エラー内容を先頭に書いてエディタで開き直す。
+fn edit_flow(album_folder: &AlbumFolder) -> Result<Value> {
+    say!(
+        "{} の編集処理を開始します。",
+        album_folder.folder().display()
+    );
+
+    let audio_files = require_audio_files(album_folder.folder())?;
+
+    let all_tags = audio_file::load_all_tags(&audio_files)?;
+    let exported_album_info = audio_file::to_album_info(&audio_files, &all_tags)?;
+
+    // 一時フォルダは処理を終えると削除される
+    let temp_folder = tempfile::tempdir()?;
+    let tags_filepath = temp_folder.path().join(*TAGS_FILENAME);
+    tags_file::write_tags_file(&tags_filepath, &exported_album_info)?;
+    let exported_contents = fs::read_to_string(&tags_filepath)?;
+
+    let mut album_info = loop {
+        editor::edit(&tags_filepath)?;
+
+        let contents = strip_edit_errors(&fs::read_to_string(&tags_filepath)?);
+        if contents.trim().is_empty() {
+            say!("tagsファイルが空なので編集処理を中止しました。");
+            return Ok(json!({ "updated": false, "files": [] }));
+        }
+        if contents == exported_contents {
+            say!("変更がなかったので編集処理を終了しました。");
+            return Ok(json!({ "updated": false, "files": [] }));
+        }
+        fs::write(&tags_filepath, &contents)?;
+
+        let error = match tags_file::load_tags_file(&tags_filepath, album_folder.folder()) {
+            Ok(album_info) => match audio_file::plan_by_album_info(&audio_files, &album_info) {
+                Ok(_) => break album_info,
+                Err(e) if e.is::<TitlesMismatchFilesError>() || e.is::<UnmatchedTracksError>() => e,
+                Err(e) => return Err(e),
+            },
+            Err(e) if e.is::<ParseTagsError>() => e,
+            Err(e) => return Err(e),
+        };
+
+        // JSON形式でも編集する人に見えるように標準エラー出力に表示する
+        eprintln!("tagsファイルに誤りがあります: {}", error);
+
+        let mut contents_with_errors = String::new();
+        for line in error.to_string().lines() {
+            contents_with_errors.push_str(EDIT_ERROR_PREFIX);
+            contents_with_errors.push_str(line);
+            contents_with_errors.push('\n');
+        }
+        contents_with_errors.push_str(&contents);
+        fs::write(&tags_filepath, contents_with_errors)?;
+    };
+
+    // アートワークはアルバムフォルダに画像ファイルがなければ今のものを引き継ぐ
+    if album_info.art_work().is_none() {
+        album_info.set_art_work(exported_album_info.art_work().cloned());
+    }
+
+    let mut journal = Journal::begin(album_folder.journal_filepath())?;
+    let updates = audio_file::update_by_album_info(&audio_files, &album_info, &mut journal)?;
+
+    let files: Vec<Value> = audio_files
+        .iter()
+        .zip(updates.iter())
+        .map(|(audio_file, (old_tags, new_tags))| {
+            import_file_json(audio_file, old_tags, new_tags, None)
+        })
+        .collect();
+
+    say!("編集処理を完了しました。");
+
+    Ok(json!({ "updated": true, "files": files }))
+}
+
+/// エディタで開き直すときにtagsファイルの先頭に書いたエラー内容を取り除く。
+fn strip_edit_errors(contents: &str) -> String {
+    let mut lines = contents.split_inclusive('\n').peekable();
+    while let Some(line) = lines.peek() {
+        if !line.starts_with(EDIT_ERROR_PREFIX) {
+            break;
+        }
+        lines.next();
+    }
+    lines.collect()
+}
+
+/// 音楽ファイルを順にリネームしてジャーナルに記録する。
+/// リネームした結果はJSONの値にして追加する。
+///
+/// ファイル名を入れ替えるリネームでも上書きしないように、ほかのファイルのリネーム先にあるファイルは
+/// 先に一時的なファイル名にリネームしておく。
+fn rename_all(
+    audio_files: &mut [AudioFile],
+    journal: &mut Journal,
+    files: &mut Vec<Value>,
+) -> Result<()> {
+    let all_tags = audio_file::load_all_tags(audio_files)?;
+
+    // ファイル名に使う項目がないファイルやリネーム先が既にあるファイルがあれば、1つもリネームしないうちに中止する
+    let new_filepaths = audio_file::renamed_filepaths(audio_files, &all_tags)?;
+    audio_file::validate_renames(audio_files, &new_filepaths)?;
+
+    let old_filepaths: Vec<PathBuf> = audio_files
+        .iter()
+        .map(|audio_file| audio_file.filepath().to_path_buf())
+        .collect();
+    for (index, audio_file) in audio_files.iter_mut().enumerate() {
+        let old_filepath = &old_filepaths[index];
+        let occupied = new_filepaths
+            .iter()
+            .enumerate()
+            .any(|(other, new_filepath)| other != index && new_filepath == old_filepath);
+        if occupied {
+            let mut temp_filename = OsString::from(".");
+            temp_filename.push(old_filepath.file_name().unwrap());
+            temp_filename.push(RENAME_TEMP_SUFFIX);
+            audio_file.rename(old_filepath.with_file_name(temp_filename))?;
+            journal.record_rename(old_filepath, audio_file.filepath());
+        }
+    }
+
+    for ((audio_file, old_filepath), new_filepath) in audio_files
+        .iter_mut()
+        .zip(old_filepaths.iter())
+        .zip(new_filepaths)
+    {
+        let current_filepath = audio_file.filepath().to_path_buf();
+        if current_filepath != new_filepath {
+            audio_file.rename(new_filepath)?;
+            journal.record_rename(&current_filepath, audio_file.filepath());
+        }
+        files.push(json!({
+            "path": path_json(old_filepath),
+            "new_path": path_json(audio_file.filepath()),
+        }));
+    }
 
     Ok(())
 }
 
+/// 取り消し処理を実行する。
+///
+/// ジャーナルに記録された最後の実行の変更を取り消す。
+fn undo_flow(album_folder: &AlbumFolder) -> Result<Value> {
+    say!(
+        "{} の取り消し処理を開始します。",
+        album_folder.folder().display()
+    );
+
+    let mut journal = Journal::load(album_folder.journal_filepath())?;
+
+    let mut changes = vec![];
+    let mut renames = vec![];
+    for change in journal.undo_last()? {
+        match change {
+            Change::Tags { filename, .. } => {
+                say!("タグを元に戻しました: {}", filename);
+                changes.push(json!({ "kind": "tags", "path": filename }));
+            }
+            Change::Rename { from, to } => {
+                say!("リネームを元に戻しました: {} -> {}", to, from);
+                changes.push(json!({ "kind": "rename", "path": to, "new_path": from }));
+                renames.push((to, from));
+            }
+        }
+    }
+    tags_file::rename_anchors(album_folder.tags_filepath(), &renames)?;
+
+    say!("取り消し処理を完了しました。");
+
+    Ok(json!({ "changes": changes }))
+}
+
+/// 表示用にファイルパスからファイル名を取り出す。
+fn filename(filepath: &Path) -> String {
+    match filepath.file_name() {
+        Some(filename) => filename.to_string_lossy().into_owned(),
+        None => filepath.display().to_string(),
+    }
+}
+
 fn require_audio_files(folder: &Path) -> Result<Vec<AudioFile>> {
     let audio_files = audio_file::find_audio_files(folder)?;
 
-    if audio_files.len() == 0 {
+    if audio_files.is_empty() {
         Err(NoTargetError::INSTANCE)?
     }
 
@@ -75,20 +849,42 @@ fn require_audio_files(folder: &Path) -> Result<Vec<AudioFile>> {
 }
 
 /// エクスポート処理を実行する。
-fn export_flow() -> Result<()> {
-    println!("エクスポート処理を開始します。");
+fn export_flow(album_folder: &AlbumFolder) -> Result<Value> {
+    say!(
+        "{} のエクスポート処理を開始します。",
+        album_folder.folder().display()
+    );
 
-    let audio_files = audio_file::find_audio_files(&TARGET_FOLDER)?;
+    let audio_files = audio_file::find_audio_files(album_folder.folder())?;
 
-    let album_info = audio_file::to_album_info(&audio_files)?;
+    let all_tags = audio_file::load_all_tags(&audio_files)?;
 
-    tags_file::write_tags_file(&TAGS_FILENAME, &album_info)?;
+    let album_info = audio_file::to_album_info(&audio_files, &all_tags)?;
 
-    tags_file::write_art_work_file(album_info.art_work())?;
+    tags_file::write_tags_file(album_folder.tags_filepath(), &album_info)?;
 
-    println!("エクスポート処理を完了しました。");
+    let art_work_filepath =
+        tags_file::write_art_work_file(album_folder.folder(), album_info.art_work())?;
 
-    Ok(())
+    say!("エクスポート処理を完了しました。");
+
+    let files: Vec<Value> = audio_files
+        .iter()
+        .zip(all_tags.iter())
+        .map(|(audio_file, tags)| {
+            json!({
+                "path": path_json(audio_file.filepath()),
+                "tags": TagsReport::from(tags),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "tags_file": path_json(album_folder.tags_filepath()),
+        "art_work_file": art_work_filepath.as_deref().map(path_json),
+        "album": album_info,
+        "files": files,
+    }))
 }
 
 /// 音楽ファイル不在エラー
@@ -100,11 +896,54 @@ pub enum NoTargetError {
     INSTANCE,
 }
 
-/// サブコマンド不正エラー
+/// サブコマンド指定位置エラー
 ///
-/// 存在しないサブコマンドを指定した場合に発生する。
+/// サブコマンドを続けて指定した場合や、オプションの後にサブコマンドを指定した場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Error)]
-pub enum NoSuchSubcommandError {
-    #[error("そのようなサブコマンドはありません: {0}")]
+pub enum SubcommandPositionError {
+    #[error("サブコマンドは1つだけ、オプションより前に指定してください: {0}")]
     INSTANCE(String),
 }
+
+/// アルバム処理失敗エラー
+///
+/// 複数のアルバムフォルダを処理して1つ以上が失敗した場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum AlbumsFailedError {
+    #[error("{0}件のアルバムフォルダの処理に失敗しました")]
+    INSTANCE(usize),
+}
+
+/// 比較対象エラー
+///
+/// 比較処理で音楽ファイルとアルバムフォルダを組み合わせて指定した場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum DiffTargetError {
+    #[error("音楽ファイル同士かアルバムフォルダ同士を指定してください")]
+    INSTANCE,
+}
+
+/// 比較不一致エラー
+///
+/// 比較処理でタグ情報が異なる音楽ファイルや対応するファイルがない音楽ファイルがあった場合に発生する。
+/// ファイルごとの比較結果を保持する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum TagsDifferError {
+    #[error("タグ情報が一致しない音楽ファイルが{0}件あります")]
+    INSTANCE(usize, Vec<Value>),
+}
+
+/// 不一致エラー
+///
+/// 照合処理でtagsファイルの内容と一致しない音楽ファイルがあった場合に発生する。
+/// ファイルごとの照合結果を保持する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum OutOfSyncError {
+    #[error("tagsファイルと一致しない音楽ファイルが{0}件あります")]
+    INSTANCE(usize, Vec<Value>),
+}
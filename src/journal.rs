@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::tags;
+use crate::tags::{Image, Tags};
+
+/// ジャーナルに残す処理の最大件数
+///
+/// 変更前のタグ情報が増え続けないように、古い処理から捨てる。
+const MAX_OPERATIONS: usize = 10;
+
+/// ジャーナル
+///
+/// インポートとリネームで行った変更をアルバムフォルダのジャーナルファイルに記録する。
+/// 変更は1回の実行ごとにまとめて記録し、取り消しは最後の実行から順に行う。
+///
+/// アルバム内の曲は同じアートワークを持つことが多いので、アートワークはタグ情報から分けて
+/// ハッシュ値ごとに1つだけ保持する。
+#[derive(Serialize, Deserialize)]
+pub struct Journal {
+    #[serde(skip)]
+    filepath: PathBuf,
+    operations: Vec<Operation>,
+    /// ハッシュ値ごとのアートワーク
+    #[serde(default)]
+    images: BTreeMap<String, Image>,
+}
+
+/// 1回の実行で行った変更
+#[derive(Default, Serialize, Deserialize)]
+struct Operation {
+    changes: Vec<Change>,
+}
+
+/// 変更
+///
+/// ファイルはアルバムフォルダからのファイル名で保持する。
+#[derive(Serialize, Deserialize)]
+pub enum Change {
+    /// タグ情報の変更(変更前のタグ情報を保持する)
+    ///
+    /// アートワークはタグ情報から除き、ハッシュ値だけを保持する。
+    Tags {
+        filename: String,
+        tags: Box<Tags>,
+        #[serde(default)]
+        art_work: Option<String>,
+    },
+    /// リネーム
+    Rename { from: String, to: String },
+}
+
+impl Journal {
+    /// ジャーナルファイルを読み込み、新しい実行の記録を開始する。
+    pub fn begin(filepath: PathBuf) -> Result<Journal> {
+        let mut journal = Journal::load(filepath)?;
+        journal.operations.push(Operation::default());
+        Ok(journal)
+    }
+
+    /// ジャーナルファイルを読み込む。
+    /// ジャーナルファイルがなければ空のジャーナルを作成する。
+    pub fn load(filepath: PathBuf) -> Result<Journal> {
+        if !filepath.exists() {
+            return Ok(Journal {
+                filepath,
+                operations: vec![],
+                images: BTreeMap::new(),
+            });
+        }
+
+        let contents = match fs::read(filepath.as_path()) {
+            Ok(contents) => contents,
+            Err(_) => Err(JournalError::INSTANCE("ジャーナルファイルが読み込めません"))?,
+        };
+        let mut journal: Journal = match serde_json::from_slice(&contents) {
+            Ok(journal) => journal,
+            Err(_) => Err(JournalError::INSTANCE("ジャーナルファイルの形式が不正です"))?,
+        };
+        journal.filepath = filepath;
+
+        Ok(journal)
+    }
+
+    /// タグ情報を変更する前に、変更前のタグ情報を記録する。
+    pub fn record_tags(&mut self, filepath: &Path, mut tags: Tags) {
+        let filename = filename(filepath);
+        let art_work = tags.art_work().map(|image| {
+            let digest = image.digest();
+            self.images
+                .entry(digest.clone())
+                .or_insert_with(|| image.clone());
+            digest
+        });
+        tags.set_art_work(None);
+        let tags = Box::new(tags);
+        self.current_changes().push(Change::Tags {
+            filename,
+            tags,
+            art_work,
+        });
+    }
+
+    /// リネームを記録する。
+    pub fn record_rename(&mut self, from: &Path, to: &Path) {
+        let from = filename(from);
+        let to = filename(to);
+        self.current_changes().push(Change::Rename { from, to });
+    }
+
+    fn current_changes(&mut self) -> &mut Vec<Change> {
+        if self.operations.is_empty() {
+            self.operations.push(Operation::default());
+        }
+        &mut self.operations.last_mut().unwrap().changes
+    }
+
+    /// ジャーナルファイルに保存する。
+    /// 記録が残っていなければジャーナルファイルを削除する。
+    pub fn save(&mut self) -> Result<()> {
+        let mut operations: Vec<Operation> = self
+            .operations
+            .drain(..)
+            .filter(|operation| !operation.changes.is_empty())
+            .collect();
+        if operations.len() > MAX_OPERATIONS {
+            operations.drain(..operations.len() - MAX_OPERATIONS);
+        }
+        self.operations = operations;
+
+        // 残った記録から参照されていないアートワークを捨てる
+        let digests: Vec<&String> = self
+            .operations
+            .iter()
+            .flat_map(|operation| operation.changes.iter())
+            .filter_map(|change| match change {
+                Change::Tags { art_work, .. } => art_work.as_ref(),
+                Change::Rename { .. } => None,
+            })
+            .collect();
+        self.images.retain(|digest, _| digests.contains(&digest));
+
+        if self.operations.is_empty() {
+            if self.filepath.exists() {
+                fs::remove_file(self.filepath.as_path())?;
+            }
+            return Ok(());
+        }
+
+        let contents = serde_json::to_vec(self)?;
+        if fs::write(self.filepath.as_path(), contents).is_err() {
+            Err(JournalError::INSTANCE(
+                "ジャーナルファイルが書き込めませんでした",
+            ))?
+        }
+
+        Ok(())
+    }
+
+    /// 最後の実行で行った変更を新しいものから順に取り消す。
+    ///
+    /// 取り消した変更を返す。
+    /// 途中で失敗した場合は、取り消せなかった変更をジャーナルに残す。
+    pub fn undo_last(&mut self) -> Result<Vec<Change>> {
+        let mut changes = match self.operations.pop() {
+            Some(operation) => operation.changes,
+            None => Err(NothingToUndoError::INSTANCE)?,
+        };
+
+        let folder = match self.filepath.parent() {
+            Some(folder) => folder.to_path_buf(),
+            None => PathBuf::new(),
+        };
+
+        let mut undone = vec![];
+        while let Some(change) = changes.pop() {
+            if let Err(e) = change.undo(folder.as_path(), &self.images) {
+                changes.push(change);
+                self.operations.push(Operation { changes });
+                self.save()?;
+                return Err(e);
+            }
+            undone.push(change);
+        }
+
+        self.save()?;
+
+        Ok(undone)
+    }
+}
+
+impl Change {
+    /// 変更を取り消す。
+    fn undo(&self, folder: &Path, images: &BTreeMap<String, Image>) -> Result<()> {
+        match self {
+            Change::Tags {
+                filename,
+                tags,
+                art_work,
+            } => {
+                let filepath = folder.join(filename);
+                let tag_io = match tags::tag_io_for(filepath.as_path()) {
+                    Some(tag_io) => tag_io,
+                    None => Err(JournalError::INSTANCE("対応していないファイル形式です"))?,
+                };
+                let mut tags = tags.clone();
+                if let Some(digest) = art_work {
+                    match images.get(digest) {
+                        Some(image) => tags.set_art_work(Some(image.clone())),
+                        None => Err(JournalError::INSTANCE(
+                            "ジャーナルファイルにアートワークがありません",
+                        ))?,
+                    }
+                }
+                tag_io.save(filepath.as_path(), &tags)?;
+            }
+            Change::Rename { from, to } => {
+                fs::rename(folder.join(to), folder.join(from))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn filename(filepath: &Path) -> String {
+    filepath.file_name().unwrap().to_str().unwrap().to_string()
+}
+
+/// ジャーナルエラー
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("{0}")]
+    INSTANCE(&'static str),
+}
+
+/// 取り消し対象不在エラー
+///
+/// ジャーナルに取り消せる変更が記録されていない場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum NothingToUndoError {
+    #[error("取り消せる変更がありません")]
+    INSTANCE,
+}
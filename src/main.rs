@@ -1,7 +1,12 @@
 mod audio_file;
+mod cli;
 mod common;
+mod editor;
 mod flow;
+mod journal;
 mod model;
+mod output;
+mod pool;
 mod tags;
 mod tags_file;
 use anyhow::Result;
@@ -1,13 +1,29 @@
-use crate::tags::Image;
+use serde::{Deserialize, Serialize};
+
+use crate::tags::{Field, Image};
 
 /// アルバム情報
-#[derive(Debug)]
+///
+/// 構造化形式のtagsファイルではこの構造をそのままシリアライズする。
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AlbumInfo {
     album: Option<String>,
     album_artist: Option<String>,
     release_date: Option<String>,
+    genre: Option<String>,
+    label: Option<String>,
+    catalog_number: Option<String>,
+    barcode: Option<String>,
+    #[serde(default)]
+    compilation: bool,
+    comment: Option<String>,
+    original_release_date: Option<String>,
+    #[serde(default)]
     discs: Vec<DiscInfo>,
+    #[serde(skip)]
     art_work: Option<Image>,
+    #[serde(default)]
+    kept_fields: Vec<Field>,
 }
 
 impl AlbumInfo {
@@ -21,8 +37,16 @@ impl AlbumInfo {
             album,
             album_artist,
             release_date,
+            genre: None,
+            label: None,
+            catalog_number: None,
+            barcode: None,
+            compilation: false,
+            comment: None,
+            original_release_date: None,
             discs: Vec::with_capacity(1),
             art_work: None,
+            kept_fields: vec![],
         }
     }
 
@@ -33,6 +57,10 @@ impl AlbumInfo {
         }
     }
 
+    pub fn set_album(&mut self, album: Option<String>) {
+        self.album = album;
+    }
+
     pub fn album_artist(&self) -> Option<&str> {
         match &self.album_artist {
             Some(album_artist) => Some(album_artist.as_str()),
@@ -40,6 +68,10 @@ impl AlbumInfo {
         }
     }
 
+    pub fn set_album_artist(&mut self, album_artist: Option<String>) {
+        self.album_artist = album_artist;
+    }
+
     pub fn release_date(&self) -> Option<&str> {
         match &self.release_date {
             Some(release_date) => Some(release_date.as_str()),
@@ -47,12 +79,99 @@ impl AlbumInfo {
         }
     }
 
+    pub fn set_release_date(&mut self, release_date: Option<String>) {
+        self.release_date = release_date;
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        match &self.genre {
+            Some(genre) => Some(genre.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_genre(&mut self, genre: Option<String>) {
+        self.genre = genre;
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        match &self.label {
+            Some(label) => Some(label.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+    }
+
+    pub fn catalog_number(&self) -> Option<&str> {
+        match &self.catalog_number {
+            Some(catalog_number) => Some(catalog_number.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_catalog_number(&mut self, catalog_number: Option<String>) {
+        self.catalog_number = catalog_number;
+    }
+
+    pub fn barcode(&self) -> Option<&str> {
+        match &self.barcode {
+            Some(barcode) => Some(barcode.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_barcode(&mut self, barcode: Option<String>) {
+        self.barcode = barcode;
+    }
+
+    pub fn compilation(&self) -> bool {
+        self.compilation
+    }
+
+    pub fn set_compilation(&mut self, compilation: bool) {
+        self.compilation = compilation;
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        match &self.comment {
+            Some(comment) => Some(comment.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
+
+    pub fn original_release_date(&self) -> Option<&str> {
+        match &self.original_release_date {
+            Some(original_release_date) => Some(original_release_date.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_original_release_date(&mut self, original_release_date: Option<String>) {
+        self.original_release_date = original_release_date;
+    }
+
     pub fn discs(&self) -> &Vec<DiscInfo> {
         &self.discs
     }
 
+    pub fn discs_mut(&mut self) -> &mut Vec<DiscInfo> {
+        &mut self.discs
+    }
+
     pub fn new_disc(&mut self) -> &mut DiscInfo {
-        let disc = DiscInfo { tracks: vec![] };
+        let disc = DiscInfo {
+            title: None,
+            artists: vec![],
+            tracks: vec![],
+            kept_fields: vec![],
+        };
         self.discs.push(disc);
         self.discs.last_mut().unwrap()
     }
@@ -67,34 +186,130 @@ impl AlbumInfo {
     pub fn set_art_work(&mut self, art_work: Option<Image>) {
         self.art_work = art_work;
     }
+
+    /// すべての音楽ファイルで現在の値を残すアルバムの項目を返す。
+    pub fn kept_fields(&self) -> &Vec<Field> {
+        &self.kept_fields
+    }
+
+    pub fn keep_field(&mut self, field: Field) {
+        self.kept_fields.push(field);
+    }
 }
 
 /// ディスク情報
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DiscInfo {
+    title: Option<String>,
+    #[serde(default)]
+    artists: Vec<String>,
+    #[serde(default)]
     tracks: Vec<TrackInfo>,
+    #[serde(default)]
+    kept_fields: Vec<Field>,
 }
 
 impl DiscInfo {
+    pub fn title(&self) -> Option<&str> {
+        match &self.title {
+            Some(title) => Some(title.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_title(&mut self, title: Option<String>) {
+        self.title = title;
+    }
+
+    /// アーティスト名のないトラックに設定するアーティスト名を返す。
+    pub fn artists(&self) -> &Vec<String> {
+        &self.artists
+    }
+
+    pub fn set_artists(&mut self, artists: Vec<String>) {
+        self.artists = artists;
+    }
+
     pub fn tracks(&self) -> &Vec<TrackInfo> {
         &self.tracks
     }
 
+    pub fn tracks_mut(&mut self) -> &mut Vec<TrackInfo> {
+        &mut self.tracks
+    }
+
     pub fn new_track(&mut self, title: Option<String>, artists: Vec<String>) -> &mut TrackInfo {
-        let track_info = TrackInfo { title, artists };
+        let track_info = TrackInfo {
+            number: None,
+            position: None,
+            title,
+            artists,
+            lyricists: vec![],
+            composers: vec![],
+            arrangers: vec![],
+            performers: vec![],
+            filename: None,
+            kept_fields: vec![],
+        };
         self.tracks.push(track_info);
         self.tracks.last_mut().unwrap()
     }
+
+    /// ディスクのすべてのトラックで音楽ファイルの現在の値を残す項目を返す。
+    /// アーティスト名はトラック情報にアーティスト名がない場合だけ残す。
+    pub fn kept_fields(&self) -> &Vec<Field> {
+        &self.kept_fields
+    }
+
+    pub fn keep_field(&mut self, field: Field) {
+        self.kept_fields.push(field);
+    }
 }
 
 /// トラック情報
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TrackInfo {
+    number: Option<usize>,
+    position: Option<String>,
     title: Option<String>,
+    #[serde(default)]
     artists: Vec<String>,
+    #[serde(default)]
+    lyricists: Vec<String>,
+    #[serde(default)]
+    composers: Vec<String>,
+    #[serde(default)]
+    arrangers: Vec<String>,
+    #[serde(default)]
+    performers: Vec<String>,
+    filename: Option<String>,
+    #[serde(default)]
+    kept_fields: Vec<Field>,
 }
 
 impl TrackInfo {
+    /// 明示されたトラック番号を返す。
+    /// 明示されていなければ前のトラックの次の番号になる。
+    pub fn number(&self) -> Option<usize> {
+        self.number
+    }
+
+    pub fn set_number(&mut self, number: Option<usize>) {
+        self.number = number;
+    }
+
+    /// レコードの面を含むトラックの位置(A1、B2など)を返す。
+    pub fn position(&self) -> Option<&str> {
+        match &self.position {
+            Some(position) => Some(position.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_position(&mut self, position: Option<String>) {
+        self.position = position;
+    }
+
     pub fn title(&self) -> Option<&str> {
         match &self.title {
             Some(title) => Some(title.as_str()),
@@ -102,7 +317,89 @@ impl TrackInfo {
         }
     }
 
+    pub fn set_title(&mut self, title: Option<String>) {
+        self.title = title;
+    }
+
     pub fn artists(&self) -> &Vec<String> {
         &self.artists
     }
+
+    pub fn set_artists(&mut self, artists: Vec<String>) {
+        self.artists = artists;
+    }
+
+    /// 対応付ける音楽ファイルのファイル名を返す。
+    /// 指定されていなければ並び順で対応付ける。
+    pub fn filename(&self) -> Option<&str> {
+        match &self.filename {
+            Some(filename) => Some(filename.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn set_filename(&mut self, filename: Option<String>) {
+        self.filename = filename;
+    }
+
+    pub fn credits(&self, role: Role) -> &Vec<String> {
+        match role {
+            Role::Lyricist => &self.lyricists,
+            Role::Composer => &self.composers,
+            Role::Arranger => &self.arrangers,
+            Role::Performer => &self.performers,
+        }
+    }
+
+    pub fn add_credit(&mut self, role: Role, name: String) {
+        match role {
+            Role::Lyricist => self.lyricists.push(name),
+            Role::Composer => self.composers.push(name),
+            Role::Arranger => self.arrangers.push(name),
+            Role::Performer => self.performers.push(name),
+        }
+    }
+
+    /// このトラックで音楽ファイルの現在の値を残す項目を返す。
+    pub fn kept_fields(&self) -> &Vec<Field> {
+        &self.kept_fields
+    }
+
+    pub fn keep_field(&mut self, field: Field) {
+        self.kept_fields.push(field);
+    }
+}
+
+/// クレジットの役割
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Lyricist,
+    Composer,
+    Arranger,
+    Performer,
+}
+
+impl Role {
+    /// すべての役割
+    pub const ALL: [Role; 4] = [
+        Role::Lyricist,
+        Role::Composer,
+        Role::Arranger,
+        Role::Performer,
+    ];
+
+    /// tagsファイルで使う役割名を返す。
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Lyricist => "作詞",
+            Role::Composer => "作曲",
+            Role::Arranger => "編曲",
+            Role::Performer => "演奏",
+        }
+    }
+
+    /// tagsファイルの役割名から役割を返す。
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
 }
@@ -0,0 +1,237 @@
+use std::io;
+use std::path::Path;
+
+use clap::ValueEnum;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::audio_file::{
+    FileAccessError, NoAudioFileError, TitlesMismatchFilesError, UnmatchedTracksError,
+    UpdateValidationError,
+};
+use crate::editor::EditorError;
+use crate::flow::{
+    AlbumsFailedError, DiffTargetError, NoTargetError, OutOfSyncError, SubcommandPositionError,
+    TagsDifferError,
+};
+use crate::journal::{JournalError, NothingToUndoError};
+use crate::model::Role;
+use crate::tags::{
+    ImageFormatError, InvalidAiffError, InvalidDffError, InvalidDsfError, InvalidOggError,
+    InvalidTagValueError, InvalidWavError, Tags,
+};
+use crate::tags_file::{LoadTagsError, ParseTagsError, WriteTagsError};
+
+/// 出力形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// 文章
+    Text,
+    /// JSON
+    Json,
+}
+
+/// 出力形式
+static FORMAT: OnceCell<Format> = OnceCell::new();
+
+/// 出力形式を設定する。
+pub fn set_format(format: Format) {
+    let _ = FORMAT.set(format);
+}
+
+/// JSON形式で出力するかを判定する。
+pub fn is_json() -> bool {
+    FORMAT.get() == Some(&Format::Json)
+}
+
+/// 文章形式で出力する場合だけ表示する。
+///
+/// JSON形式では標準出力をJSONだけにするため表示しない。
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_json() {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use say;
+
+/// JSONを標準出力に表示する。
+pub fn print_json(value: &Value) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+/// エラーの種類を表す識別子を返す。
+///
+/// 原因をたどって最初に見つかった既知のエラーの識別子にする。
+/// 自動処理で判定に使うので、一度決めた識別子は変えないこと。
+pub fn error_code(e: &anyhow::Error) -> &'static str {
+    for cause in e.chain() {
+        let code = if cause.is::<NoTargetError>() {
+            "no_target"
+        } else if cause.is::<SubcommandPositionError>() {
+            "subcommand_position"
+        } else if cause.is::<AlbumsFailedError>() {
+            "albums_failed"
+        } else if cause.is::<OutOfSyncError>() {
+            "out_of_sync"
+        } else if cause.is::<TagsDifferError>() {
+            "tags_differ"
+        } else if cause.is::<DiffTargetError>() {
+            "diff_target"
+        } else if cause.is::<TitlesMismatchFilesError>() {
+            "titles_mismatch_files"
+        } else if cause.is::<UnmatchedTracksError>() {
+            "unmatched_tracks"
+        } else if cause.is::<UpdateValidationError>() {
+            "update_validation"
+        } else if cause.is::<FileAccessError>() {
+            "file_access"
+        } else if cause.is::<NoAudioFileError>() {
+            "no_audio_file"
+        } else if cause.is::<LoadTagsError>() {
+            "load_tags_file"
+        } else if cause.is::<ParseTagsError>() {
+            "parse_tags_file"
+        } else if cause.is::<WriteTagsError>() {
+            "write_tags_file"
+        } else if cause.is::<JournalError>() {
+            "journal"
+        } else if cause.is::<NothingToUndoError>() {
+            "nothing_to_undo"
+        } else if cause.is::<EditorError>() {
+            "editor"
+        } else if cause.is::<InvalidTagValueError>() {
+            "invalid_tag_value"
+        } else if cause.is::<ImageFormatError>() {
+            "image_format"
+        } else if cause.is::<id3::Error>()
+            || cause.is::<metaflac::Error>()
+            || cause.is::<mp4ameta::Error>()
+            || cause.is::<dsf::Error>()
+            || cause.is::<InvalidAiffError>()
+            || cause.is::<InvalidDffError>()
+            || cause.is::<InvalidDsfError>()
+            || cause.is::<InvalidOggError>()
+            || cause.is::<InvalidWavError>()
+            || cause.is::<ogg::OggReadError>()
+        {
+            "tag_format"
+        } else if cause.is::<io::Error>() {
+            "io"
+        } else {
+            continue;
+        };
+        return code;
+    }
+
+    "unknown"
+}
+
+/// エラーをJSONの値にする。
+pub fn error_json(e: &anyhow::Error) -> Value {
+    let mut value = json!({
+        "code": error_code(e),
+        "message": format!("{:#}", e),
+    });
+
+    if let Some(UpdateValidationError::INSTANCE(problems)) = e.downcast_ref() {
+        value["files"] = serde_json::to_value(problems).unwrap();
+    }
+    if let Some(TitlesMismatchFilesError::INSTANCE(discs)) = e.downcast_ref() {
+        value["discs"] = serde_json::to_value(discs).unwrap();
+    }
+    if let Some(ParseTagsError::INSTANCE(diagnostics)) = e.downcast_ref() {
+        value["diagnostics"] = serde_json::to_value(diagnostics).unwrap();
+    }
+    if let Some(UnmatchedTracksError::INSTANCE(tracks, files)) = e.downcast_ref() {
+        value["tracks"] = json!(tracks);
+        value["files"] = json!(files);
+    }
+    if let Some(OutOfSyncError::INSTANCE(_, files)) = e.downcast_ref() {
+        value["files"] = Value::Array(files.clone());
+    }
+    if let Some(TagsDifferError::INSTANCE(_, files)) = e.downcast_ref() {
+        value["files"] = Value::Array(files.clone());
+    }
+
+    value
+}
+
+/// ファイルパスをJSONの値にする。
+pub fn path_json(filepath: &Path) -> Value {
+    Value::String(filepath.display().to_string())
+}
+
+/// 出力用のタグ情報
+///
+/// アートワークは画像データの代わりに形式、サイズ、ハッシュ値を出力する。
+#[derive(Serialize)]
+pub struct TagsReport<'a> {
+    album: Option<&'a str>,
+    album_artist: Option<&'a str>,
+    release_date: Option<&'a str>,
+    genre: Option<&'a str>,
+    label: Option<&'a str>,
+    catalog_number: Option<&'a str>,
+    barcode: Option<&'a str>,
+    compilation: bool,
+    comment: Option<&'a str>,
+    original_release_date: Option<&'a str>,
+    disc_number: Option<usize>,
+    disc_title: Option<&'a str>,
+    number_of_discs: Option<usize>,
+    track_number: Option<usize>,
+    track_position: Option<&'a str>,
+    number_of_tracks: Option<usize>,
+    title: Option<&'a str>,
+    artists: &'a [String],
+    lyricists: &'a [String],
+    composers: &'a [String],
+    arrangers: &'a [String],
+    performers: &'a [String],
+    art_work: Option<ArtWorkReport>,
+}
+
+/// 出力用のアートワーク情報
+#[derive(Serialize)]
+struct ArtWorkReport {
+    mime: &'static str,
+    size: usize,
+    sha256: String,
+}
+
+impl<'a> From<&'a Tags> for TagsReport<'a> {
+    fn from(tags: &'a Tags) -> TagsReport<'a> {
+        TagsReport {
+            album: tags.album(),
+            album_artist: tags.album_artist(),
+            release_date: tags.release_date(),
+            genre: tags.genre(),
+            label: tags.label(),
+            catalog_number: tags.catalog_number(),
+            barcode: tags.barcode(),
+            compilation: tags.compilation(),
+            comment: tags.comment(),
+            original_release_date: tags.original_release_date(),
+            disc_number: tags.disc_number(),
+            disc_title: tags.disc_title(),
+            number_of_discs: tags.number_of_discs(),
+            track_number: tags.track_number(),
+            track_position: tags.track_position(),
+            number_of_tracks: tags.number_of_tracks(),
+            title: tags.title(),
+            artists: tags.artists(),
+            lyricists: tags.credits(Role::Lyricist),
+            composers: tags.credits(Role::Composer),
+            arrangers: tags.credits(Role::Arranger),
+            performers: tags.credits(Role::Performer),
+            art_work: tags.art_work().map(|image| ArtWorkReport {
+                mime: image.format().mime(),
+                size: image.data().len(),
+                sha256: image.digest(),
+            }),
+        }
+    }
+}
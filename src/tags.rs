@@ -3,26 +3,44 @@ use std::path::Path;
 
 use anyhow::Result;
 use imghdr;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
-use crate::model::{AlbumInfo, DiscInfo, TrackInfo};
+use crate::model::{AlbumInfo, DiscInfo, Role, TrackInfo};
+use crate::tags::aiff_tag_io::AiffIOImpl;
+use crate::tags::dff_tag_io::DffIOImpl;
 use crate::tags::dsf_tag_io::DsfIOImpl;
 use crate::tags::flac_tag_io::FlacIOImpl;
 use crate::tags::id3_tag_io::ID3IOImpl;
 use crate::tags::m4a_tag_io::M4AIOImpl;
+use crate::tags::ogg_tag_io::OggIOImpl;
+use crate::tags::wav_tag_io::WavIOImpl;
 
+mod aiff_tag_io;
+mod dff_tag_io;
 mod dsf_tag_io;
+mod file_common;
 mod flac_tag_io;
 mod id3_common;
 mod id3_tag_io;
 mod m4a_tag_io;
+mod ogg_tag_io;
+mod vorbis_common;
+mod wav_tag_io;
 
 /// タグIO
 ///
 /// タグの参照/設定を行う。
-pub trait TagIO {
+/// 複数のファイルを並列に処理するのでスレッド間で共有できること。
+pub trait TagIO: Send + Sync {
     fn load(&self, filepath: &Path) -> Result<Tags>;
     fn save(&self, filepath: &Path, tags: &Tags) -> Result<()>;
+
+    /// タグ情報がこの形式で書き込めるかを検証する。
+    fn validate(&self, _tags: &Tags) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// 指定されたファイルの形式に対応するタグIO実装を返す。
@@ -32,27 +50,49 @@ pub fn tag_io_for(filepath: &Path) -> Option<Box<dyn TagIO>> {
         None => return None,
     };
     match extension {
+        "aif" | "aiff" => Some(Box::new(AiffIOImpl)),
+        "dff" => Some(Box::new(DffIOImpl)),
         "dsf" => Some(Box::new(DsfIOImpl)),
         "flac" => Some(Box::new(FlacIOImpl)),
         "m4a" => Some(Box::new(M4AIOImpl)),
         "mp3" => Some(Box::new(ID3IOImpl)),
-        "wav" => Some(Box::new(ID3IOImpl)),
+        "ogg" | "oga" | "opus" => Some(Box::new(OggIOImpl)),
+        "wav" => Some(Box::new(WavIOImpl)),
         _ => None,
     }
 }
 
 /// タグ情報
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Tags {
     album: Option<String>,
     album_artist: Option<String>,
     release_date: Option<String>,
+    genre: Option<String>,
+    label: Option<String>,
+    catalog_number: Option<String>,
+    barcode: Option<String>,
+    #[serde(default)]
+    compilation: bool,
+    comment: Option<String>,
+    original_release_date: Option<String>,
     art_work: Option<Image>,
     number_of_discs: Option<usize>,
     disc_number: Option<usize>,
+    disc_title: Option<String>,
     number_of_tracks: Option<usize>,
     track_number: Option<usize>,
+    track_position: Option<String>,
     title: Option<String>,
     artists: Vec<String>,
+    #[serde(default)]
+    lyricists: Vec<String>,
+    #[serde(default)]
+    composers: Vec<String>,
+    #[serde(default)]
+    arrangers: Vec<String>,
+    #[serde(default)]
+    performers: Vec<String>,
 }
 
 impl Tags {
@@ -61,18 +101,31 @@ impl Tags {
             album: None,
             album_artist: None,
             release_date: None,
+            genre: None,
+            label: None,
+            catalog_number: None,
+            barcode: None,
+            compilation: false,
+            comment: None,
+            original_release_date: None,
             art_work: None,
             number_of_discs: None,
             disc_number: None,
+            disc_title: None,
             number_of_tracks: None,
             track_number: None,
+            track_position: None,
             title: None,
             artists: vec![],
+            lyricists: vec![],
+            composers: vec![],
+            arrangers: vec![],
+            performers: vec![],
         }
     }
 
     pub fn album(&self) -> Option<&str> {
-        self.album.as_ref().map(String::as_str)
+        self.album.as_deref()
     }
 
     pub fn set_album(&mut self, album: Option<String>) {
@@ -80,7 +133,7 @@ impl Tags {
     }
 
     pub fn album_artist(&self) -> Option<&str> {
-        self.album_artist.as_ref().map(String::as_str)
+        self.album_artist.as_deref()
     }
 
     pub fn set_album_artist(&mut self, album_artist: Option<String>) {
@@ -88,13 +141,69 @@ impl Tags {
     }
 
     pub fn release_date(&self) -> Option<&str> {
-        self.release_date.as_ref().map(String::as_str)
+        self.release_date.as_deref()
     }
 
     pub fn set_release_date(&mut self, release_date: Option<String>) {
         self.release_date = release_date;
     }
 
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    pub fn set_genre(&mut self, genre: Option<String>) {
+        self.genre = genre;
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+    }
+
+    pub fn catalog_number(&self) -> Option<&str> {
+        self.catalog_number.as_deref()
+    }
+
+    pub fn set_catalog_number(&mut self, catalog_number: Option<String>) {
+        self.catalog_number = catalog_number;
+    }
+
+    pub fn barcode(&self) -> Option<&str> {
+        self.barcode.as_deref()
+    }
+
+    pub fn set_barcode(&mut self, barcode: Option<String>) {
+        self.barcode = barcode;
+    }
+
+    pub fn compilation(&self) -> bool {
+        self.compilation
+    }
+
+    pub fn set_compilation(&mut self, compilation: bool) {
+        self.compilation = compilation;
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    pub fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
+
+    pub fn original_release_date(&self) -> Option<&str> {
+        self.original_release_date.as_deref()
+    }
+
+    pub fn set_original_release_date(&mut self, original_release_date: Option<String>) {
+        self.original_release_date = original_release_date;
+    }
+
     pub fn art_work(&self) -> Option<&Image> {
         self.art_work.as_ref()
     }
@@ -119,6 +228,14 @@ impl Tags {
         self.disc_number = disc_number;
     }
 
+    pub fn disc_title(&self) -> Option<&str> {
+        self.disc_title.as_deref()
+    }
+
+    pub fn set_disc_title(&mut self, disc_title: Option<String>) {
+        self.disc_title = disc_title;
+    }
+
     pub fn number_of_tracks(&self) -> Option<usize> {
         self.number_of_tracks
     }
@@ -135,8 +252,16 @@ impl Tags {
         self.track_number = track_number;
     }
 
+    pub fn track_position(&self) -> Option<&str> {
+        self.track_position.as_deref()
+    }
+
+    pub fn set_track_position(&mut self, track_position: Option<String>) {
+        self.track_position = track_position;
+    }
+
     pub fn title(&self) -> Option<&str> {
-        self.title.as_ref().map(String::as_str)
+        self.title.as_deref()
     }
 
     pub fn set_title(&mut self, title: Option<String>) {
@@ -151,11 +276,157 @@ impl Tags {
         self.artists.push(artist);
     }
 
+    pub fn credits(&self, role: Role) -> &Vec<String> {
+        match role {
+            Role::Lyricist => &self.lyricists,
+            Role::Composer => &self.composers,
+            Role::Arranger => &self.arrangers,
+            Role::Performer => &self.performers,
+        }
+    }
+
+    pub fn add_credit(&mut self, role: Role, name: String) {
+        match role {
+            Role::Lyricist => self.lyricists.push(name),
+            Role::Composer => self.composers.push(name),
+            Role::Arranger => self.arrangers.push(name),
+            Role::Performer => self.performers.push(name),
+        }
+    }
+
+    /// 表示や比較のために項目と値の一覧を返す。
+    pub fn fields(&self) -> Vec<(Field, Option<String>)> {
+        let join = |names: &Vec<String>| {
+            if names.is_empty() {
+                None
+            } else {
+                Some(names.join("//"))
+            }
+        };
+
+        vec![
+            (Field::Album, self.album.clone()),
+            (Field::AlbumArtist, self.album_artist.clone()),
+            (Field::ReleaseDate, self.release_date.clone()),
+            (Field::Genre, self.genre.clone()),
+            (Field::Label, self.label.clone()),
+            (Field::CatalogNumber, self.catalog_number.clone()),
+            (Field::Barcode, self.barcode.clone()),
+            (
+                Field::Compilation,
+                self.compilation.then(|| String::from("true")),
+            ),
+            (Field::Comment, self.comment.clone()),
+            (
+                Field::OriginalReleaseDate,
+                self.original_release_date.clone(),
+            ),
+            (Field::DiscNumber, self.disc_number.map(|n| n.to_string())),
+            (Field::DiscTitle, self.disc_title.clone()),
+            (
+                Field::NumberOfDiscs,
+                self.number_of_discs.map(|n| n.to_string()),
+            ),
+            (Field::TrackNumber, self.track_number.map(|n| n.to_string())),
+            (Field::TrackPosition, self.track_position.clone()),
+            (
+                Field::NumberOfTracks,
+                self.number_of_tracks.map(|n| n.to_string()),
+            ),
+            (Field::Title, self.title.clone()),
+            (Field::Artists, join(&self.artists)),
+            (Field::Lyricists, join(&self.lyricists)),
+            (Field::Composers, join(&self.composers)),
+            (Field::Arrangers, join(&self.arrangers)),
+            (Field::Performers, join(&self.performers)),
+            (
+                Field::ArtWork,
+                self.art_work.as_ref().map(|image| {
+                    format!(
+                        "{} {}バイト sha256:{}",
+                        image.format.mime(),
+                        image.data.len(),
+                        image.digest()
+                    )
+                }),
+            ),
+        ]
+    }
+
+    /// 値が異なる項目の一覧を返す。
+    /// アーティスト名などの名前の一覧は、表示用に連結した文字列ではなく名前ごとに比較する。
+    /// アートワークは画像データのハッシュ値で比較する。
+    pub fn differences(&self, other: &Tags) -> Vec<Field> {
+        self.fields()
+            .into_iter()
+            .zip(other.fields())
+            .filter(|((field, value), (_, other_value))| {
+                match (self.names(*field), other.names(*field)) {
+                    (Some(names), Some(other_names)) => names != other_names,
+                    _ => value != other_value,
+                }
+            })
+            .map(|((field, _), _)| field)
+            .collect()
+    }
+
+    /// 名前の一覧を値に持つ項目なら、その一覧を返す。
+    fn names(&self, field: Field) -> Option<&Vec<String>> {
+        match field {
+            Field::Artists => Some(&self.artists),
+            Field::Lyricists => Some(&self.lyricists),
+            Field::Composers => Some(&self.composers),
+            Field::Arrangers => Some(&self.arrangers),
+            Field::Performers => Some(&self.performers),
+            _ => None,
+        }
+    }
+
+    /// 指定された項目だけ別のタグ情報の値で置き換える。
+    pub fn keep_fields(&mut self, original: &Tags, fields: &[Field]) {
+        for field in fields {
+            match field {
+                Field::Album => self.album = original.album.clone(),
+                Field::AlbumArtist => self.album_artist = original.album_artist.clone(),
+                Field::ReleaseDate => self.release_date = original.release_date.clone(),
+                Field::Genre => self.genre = original.genre.clone(),
+                Field::Label => self.label = original.label.clone(),
+                Field::CatalogNumber => self.catalog_number = original.catalog_number.clone(),
+                Field::Barcode => self.barcode = original.barcode.clone(),
+                Field::Compilation => self.compilation = original.compilation,
+                Field::Comment => self.comment = original.comment.clone(),
+                Field::OriginalReleaseDate => {
+                    self.original_release_date = original.original_release_date.clone()
+                }
+                Field::DiscNumber => self.disc_number = original.disc_number,
+                Field::DiscTitle => self.disc_title = original.disc_title.clone(),
+                Field::NumberOfDiscs => self.number_of_discs = original.number_of_discs,
+                Field::TrackNumber => self.track_number = original.track_number,
+                Field::TrackPosition => self.track_position = original.track_position.clone(),
+                Field::NumberOfTracks => self.number_of_tracks = original.number_of_tracks,
+                Field::Title => self.title = original.title.clone(),
+                Field::Artists => self.artists = original.artists.clone(),
+                Field::Lyricists => self.lyricists = original.lyricists.clone(),
+                Field::Composers => self.composers = original.composers.clone(),
+                Field::Arrangers => self.arrangers = original.arrangers.clone(),
+                Field::Performers => self.performers = original.performers.clone(),
+                Field::ArtWork => self.art_work = original.art_work.clone(),
+            }
+        }
+    }
+
     /// アルバム情報でタグ情報を更新する。
     pub fn update_album_info(&mut self, album_info: &AlbumInfo) {
         self.set_album(album_info.album().map(String::from));
         self.set_album_artist(album_info.album_artist().map(String::from));
         self.set_release_date(album_info.release_date().map(String::from));
+        self.set_genre(album_info.genre().map(String::from));
+        self.set_label(album_info.label().map(String::from));
+        self.set_catalog_number(album_info.catalog_number().map(String::from));
+        self.set_barcode(album_info.barcode().map(String::from));
+        self.set_compilation(album_info.compilation());
+        self.set_comment(album_info.comment().map(String::from));
+        self.set_original_release_date(album_info.original_release_date().map(String::from));
         match album_info.art_work() {
             Some(image) => self.set_art_work(Some(image.clone())),
             None => self.set_art_work(None),
@@ -164,23 +435,95 @@ impl Tags {
     }
 
     /// ディスク情報でタグ情報を更新する。
+    ///
+    /// ディスクのアーティスト名は、トラック情報で更新するときにアーティスト名がなければ使われる。
     pub fn update_disc_info(&mut self, disc_number: usize, disc_info: &DiscInfo) {
         self.set_disc_number(Some(disc_number));
+        self.set_disc_title(disc_info.title().map(String::from));
         self.set_number_of_tracks(Some(disc_info.tracks().len()));
+        self.artists = disc_info.artists().clone();
     }
 
     /// トラック情報でタグ情報を更新する。
     pub fn update_track_info(&mut self, track_number: usize, track_info: &TrackInfo) {
         self.set_track_number(Some(track_number));
+        self.set_track_position(track_info.position().map(String::from));
         self.set_title(track_info.title().map(String::from));
-        for artist in track_info.artists() {
-            self.add_artist(artist.clone());
+        if !track_info.artists().is_empty() {
+            self.artists = track_info.artists().clone();
+        }
+        for role in Role::ALL {
+            for name in track_info.credits(role) {
+                self.add_credit(role, name.clone());
+            }
+        }
+    }
+}
+
+/// タグ情報の項目
+///
+/// シリアライズするとスネークケースの識別子になる。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Album,
+    AlbumArtist,
+    ReleaseDate,
+    Genre,
+    Label,
+    CatalogNumber,
+    Barcode,
+    Compilation,
+    Comment,
+    OriginalReleaseDate,
+    DiscNumber,
+    DiscTitle,
+    NumberOfDiscs,
+    TrackNumber,
+    TrackPosition,
+    NumberOfTracks,
+    Title,
+    Artists,
+    Lyricists,
+    Composers,
+    Arrangers,
+    Performers,
+    ArtWork,
+}
+
+impl Field {
+    /// 表示用の項目名を返す。
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Album => "アルバム名",
+            Field::AlbumArtist => "アルバムアーティスト名",
+            Field::ReleaseDate => "発売日",
+            Field::Genre => "ジャンル",
+            Field::Label => "レーベル",
+            Field::CatalogNumber => "カタログ番号",
+            Field::Barcode => "バーコード",
+            Field::Compilation => "コンピレーション",
+            Field::Comment => "コメント",
+            Field::OriginalReleaseDate => "オリジナル発売日",
+            Field::DiscNumber => "ディスク番号",
+            Field::DiscTitle => "ディスクタイトル",
+            Field::NumberOfDiscs => "総ディスク数",
+            Field::TrackNumber => "トラック番号",
+            Field::TrackPosition => "トラック位置",
+            Field::NumberOfTracks => "総トラック数",
+            Field::Title => "タイトル",
+            Field::Artists => "アーティスト名",
+            Field::Lyricists => "作詞",
+            Field::Composers => "作曲",
+            Field::Arrangers => "編曲",
+            Field::Performers => "演奏",
+            Field::ArtWork => "アートワーク",
         }
     }
 }
 
 /// 画像フォーマット
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum ImageFormat {
     JPEG,
     PNG,
@@ -245,9 +588,10 @@ impl ImageFormat {
 }
 
 /// 画像
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Image {
     format: ImageFormat,
+    #[serde(with = "base64_data")]
     data: Vec<u8>,
 }
 
@@ -264,6 +608,14 @@ impl Image {
     pub fn data(&self) -> &Vec<u8> {
         &self.data
     }
+
+    /// 画像データのSHA-256ハッシュ値を16進数の文字列で返す。
+    pub fn digest(&self) -> String {
+        Sha256::digest(&self.data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
 }
 
 impl Debug for Image {
@@ -277,6 +629,82 @@ impl Debug for Image {
     }
 }
 
+/// 画像データをBase64の文字列としてシリアライズする。
+mod base64_data {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// タグ値エラー
+///
+/// タグ情報の値がファイル形式で表現できない場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum InvalidTagValueError {
+    #[error("{0}")]
+    INSTANCE(String),
+}
+
+/// AIFF形式エラー
+///
+/// AIFFファイルのチャンク構造が壊れている場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum InvalidAiffError {
+    #[error("{0}")]
+    INSTANCE(&'static str),
+}
+
+/// DSDIFF形式エラー
+///
+/// DSDIFFファイルのチャンク構造が壊れている場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum InvalidDffError {
+    #[error("{0}")]
+    INSTANCE(&'static str),
+}
+
+/// DSF形式エラー
+///
+/// DSFファイルのチャンク構造が壊れている場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum InvalidDsfError {
+    #[error("{0}")]
+    INSTANCE(&'static str),
+}
+
+/// Ogg形式エラー
+///
+/// Oggファイルが対応していないコーデックである、またはヘッダーが壊れている場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum InvalidOggError {
+    #[error("{0}")]
+    INSTANCE(&'static str),
+}
+
+/// WAV形式エラー
+///
+/// WAVファイルのチャンク構造が壊れている場合に発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum InvalidWavError {
+    #[error("{0}")]
+    INSTANCE(&'static str),
+}
+
 /// 画像フォーマットエラー
 ///
 /// 非対応の形式である、または画像ファイルでないデータやファイルを画像として処理しようとすると発生する。
@@ -285,3 +713,20 @@ pub enum ImageFormatError {
     #[error("対応していない画像フォーマットです")]
     INSTANCE,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn differences_compares_names_one_by_one() {
+        let mut joined = Tags::new();
+        joined.add_artist("A//B".to_string());
+        let mut separated = Tags::new();
+        separated.add_artist("A".to_string());
+        separated.add_artist("B".to_string());
+
+        assert_eq!(joined.differences(&separated), vec![Field::Artists]);
+        assert!(separated.differences(&separated.clone()).is_empty());
+    }
+}
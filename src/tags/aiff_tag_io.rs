@@ -0,0 +1,194 @@
+use std::fs;
+use std::path::Path;
+
+use id3::{Tag, Version};
+
+use crate::tags::id3_common::{build_id3, load_id3, validate_id3};
+use crate::tags::{InvalidAiffError, TagIO, Tags};
+use anyhow::Result;
+
+/// ファイル全体のチャンクのID
+const FORM_CHUNK_ID: &[u8; 4] = b"FORM";
+
+/// タイトルのチャンクのID
+const NAME_CHUNK_ID: &[u8; 4] = b"NAME";
+
+/// 作者(アーティスト名)のチャンクのID
+const AUTH_CHUNK_ID: &[u8; 4] = b"AUTH";
+
+/// 注釈(コメント)のチャンクのID
+const ANNO_CHUNK_ID: &[u8; 4] = b"ANNO";
+
+/// チャンクヘッダー(IDとサイズ)の長さ
+const CHUNK_HEADER_SIZE: usize = 8;
+
+/// AIFFタグIO実装
+///
+/// ID3チャンクのID3v2タグにタグ情報を持つ。
+/// ID3チャンクがなければ、NAME、AUTH、ANNOチャンクのタイトル、アーティスト名、コメントを読み込む。
+pub struct AiffIOImpl;
+
+impl TagIO for AiffIOImpl {
+    fn load(&self, filepath: &Path) -> Result<Tags> {
+        if let Some(file_tags) = id3::no_tag_ok(Tag::read_from_path(filepath))? {
+            return load_id3(&file_tags);
+        }
+
+        let contents = fs::read(filepath)?;
+        let chunks = parse_chunks(&contents)?;
+
+        let mut tags = Tags::new();
+        tags.set_title(find_text(&chunks, NAME_CHUNK_ID));
+        if let Some(artist) = find_text(&chunks, AUTH_CHUNK_ID) {
+            tags.add_artist(artist);
+        }
+        tags.set_comment(find_text(&chunks, ANNO_CHUNK_ID));
+
+        Ok(tags)
+    }
+
+    fn save(&self, filepath: &Path, tags: &Tags) -> Result<()> {
+        let file_tags = build_id3(tags)?;
+
+        file_tags.write_to_path(filepath, Version::Id3v24)?;
+
+        Ok(())
+    }
+
+    fn validate(&self, tags: &Tags) -> Result<()> {
+        validate_id3(tags)
+    }
+}
+
+/// FORMチャンク直下のチャンクを(ID、データ)の一覧にする。
+fn parse_chunks(contents: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let form_type = contents.get(8..12);
+    if !contents.starts_with(FORM_CHUNK_ID) || !matches!(form_type, Some(b"AIFF") | Some(b"AIFC")) {
+        Err(InvalidAiffError::INSTANCE("AIFFファイルではありません"))?
+    }
+
+    let form_size = u32::from_be_bytes(contents[4..8].try_into().unwrap()) as usize;
+    let form_end = (CHUNK_HEADER_SIZE + form_size).min(contents.len());
+
+    let mut chunks = vec![];
+    let mut offset = 12;
+    while offset + CHUNK_HEADER_SIZE <= form_end {
+        let id = contents[offset..offset + 4].try_into().unwrap();
+        let size =
+            u32::from_be_bytes(contents[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let start = offset + CHUNK_HEADER_SIZE;
+        if start + size > form_end {
+            Err(InvalidAiffError::INSTANCE("チャンクが途中で切れています"))?
+        }
+        chunks.push((id, &contents[start..start + size]));
+        offset = start + size + size % 2;
+    }
+
+    Ok(chunks)
+}
+
+/// テキストのチャンクの文字列を返す。
+///
+/// 文字コードの規定はASCIIだが、UTF-8として読む。
+fn find_text(chunks: &[([u8; 4], &[u8])], id: &[u8; 4]) -> Option<String> {
+    let (_, data) = chunks.iter().find(|(chunk_id, _)| chunk_id == id)?;
+    let text = String::from_utf8_lossy(data)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SSNDチャンクの音声データ
+    const SOUND_DATA: [u8; 13] = [0x69; 13];
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_be_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn aiff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = [b"AIFF".to_vec(), chunks.concat()].concat();
+        [
+            FORM_CHUNK_ID.to_vec(),
+            (body.len() as u32).to_be_bytes().to_vec(),
+            body,
+        ]
+        .concat()
+    }
+
+    fn aiff_with_text_chunks() -> Vec<u8> {
+        aiff(&[
+            chunk(b"COMM", &[0; 18]),
+            chunk(NAME_CHUNK_ID, b"Title"),
+            chunk(AUTH_CHUNK_ID, b"Artist\0"),
+            chunk(ANNO_CHUNK_ID, b"Comment"),
+            chunk(b"SSND", &SOUND_DATA),
+        ])
+    }
+
+    fn form_size(contents: &[u8]) -> usize {
+        u32::from_be_bytes(contents[4..8].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn load_reads_text_chunks_without_id3() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), aiff_with_text_chunks()).unwrap();
+
+        let tags = AiffIOImpl.load(file.path()).unwrap();
+
+        assert_eq!(tags.title(), Some("Title"));
+        assert_eq!(tags.artists(), &vec!["Artist".to_string()]);
+        assert_eq!(tags.comment(), Some("Comment"));
+    }
+
+    #[test]
+    fn save_and_load_id3_chunk() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), aiff_with_text_chunks()).unwrap();
+
+        let mut tags = Tags::new();
+        tags.set_title(Some("A fairly long new title".to_string()));
+        tags.add_artist("New Artist".to_string());
+        AiffIOImpl.save(file.path(), &tags).unwrap();
+
+        let contents = fs::read(file.path()).unwrap();
+        assert_eq!(form_size(&contents), contents.len() - CHUNK_HEADER_SIZE);
+        let chunks = parse_chunks(&contents).unwrap();
+        assert!(chunks.iter().any(|(id, _)| id == b"ID3 " || id == b"id3 "));
+        let (_, sound_data) = chunks.iter().find(|(id, _)| id == b"SSND").unwrap();
+        assert_eq!(*sound_data, SOUND_DATA);
+        let loaded = AiffIOImpl.load(file.path()).unwrap();
+        assert_eq!(loaded.title(), Some("A fairly long new title"));
+        assert_eq!(loaded.artists(), &vec!["New Artist".to_string()]);
+        let first_len = contents.len();
+
+        // 2回目の保存では既存のID3チャンクを置き換える
+        AiffIOImpl.save(file.path(), &Tags::new()).unwrap();
+        let contents = fs::read(file.path()).unwrap();
+        assert_eq!(form_size(&contents), contents.len() - CHUNK_HEADER_SIZE);
+        let chunks = parse_chunks(&contents).unwrap();
+        let id3_chunks = chunks
+            .iter()
+            .filter(|(id, _)| id == b"ID3 " || id == b"id3 ")
+            .count();
+        assert_eq!(id3_chunks, 1);
+        assert!(contents.len() <= first_len);
+        assert_eq!(AiffIOImpl.load(file.path()).unwrap().title(), None);
+    }
+}
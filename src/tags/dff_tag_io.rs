@@ -0,0 +1,347 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use id3::{Tag, Version};
+
+use crate::tags::file_common::replace_file;
+use crate::tags::id3_common::{build_id3, load_id3, validate_id3};
+use crate::tags::{InvalidDffError, TagIO, Tags};
+use anyhow::Result;
+
+/// ファイル全体のチャンクのID
+const FORM_CHUNK_ID: &[u8; 4] = b"FRM8";
+
+/// DSDIFFのフォームタイプ
+const FORM_TYPE: &[u8; 4] = b"DSD ";
+
+/// ID3v2タグを格納するチャンクのID
+const ID3_CHUNK_ID: &[u8; 4] = b"ID3 ";
+
+/// 編集マスター情報のチャンクのID
+const DIIN_CHUNK_ID: &[u8; 4] = b"DIIN";
+
+/// 編集マスター情報のタイトルのチャンクのID
+const DITI_CHUNK_ID: &[u8; 4] = b"DITI";
+
+/// 編集マスター情報のアーティスト名のチャンクのID
+const DIAR_CHUNK_ID: &[u8; 4] = b"DIAR";
+
+/// チャンクヘッダー(IDとサイズ)の長さ
+const CHUNK_HEADER_SIZE: u64 = 12;
+
+/// FRM8チャンクのヘッダーとフォームタイプの長さ(最初のチャンクの位置)
+const FIRST_CHUNK_OFFSET: u64 = CHUNK_HEADER_SIZE + 4;
+
+/// DFFタグIO実装
+///
+/// DSDIFFには標準のタグがないので、多くのソフトと同じくID3チャンクのID3v2タグにタグ情報を持つ。
+/// ID3チャンクがなければ、DIINチャンクのタイトルとアーティスト名を読み込む。
+pub struct DffIOImpl;
+
+impl TagIO for DffIOImpl {
+    fn load(&self, filepath: &Path) -> Result<Tags> {
+        let mut file = File::open(filepath)?;
+        let chunks = read_chunks(&mut file)?;
+
+        if let Some(chunk) = find_chunk(&chunks, ID3_CHUNK_ID) {
+            let file_tags = Tag::read_from2(Cursor::new(chunk.read_data(&mut file)?))?;
+            return load_id3(&file_tags);
+        }
+
+        let mut tags = Tags::new();
+        if let Some(chunk) = find_chunk(&chunks, DIIN_CHUNK_ID) {
+            let diin_data = chunk.read_data(&mut file)?;
+            let diin_chunks = parse_chunks(&diin_data)?;
+            if let Some(title) = find_text(&diin_chunks, DITI_CHUNK_ID) {
+                tags.set_title(Some(title));
+            }
+            if let Some(artist) = find_text(&diin_chunks, DIAR_CHUNK_ID) {
+                tags.add_artist(artist);
+            }
+        }
+
+        Ok(tags)
+    }
+
+    /// ID3チャンクをファイル末尾に書き込み、FRM8チャンクのサイズを更新する。
+    ///
+    /// 既存のID3チャンクが末尾になければ、ID3チャンクを除いてファイルを作り直す。
+    fn save(&self, filepath: &Path, tags: &Tags) -> Result<()> {
+        let mut id3_data = vec![];
+        build_id3(tags)?.write_to(&mut id3_data, Version::Id3v24)?;
+
+        let chunks = read_chunks(&mut File::open(filepath)?)?;
+        let id3_chunk = find_chunk(&chunks, ID3_CHUNK_ID);
+        let is_last = |chunk: &Chunk| chunks.last().map(|last| last.offset) == Some(chunk.offset);
+
+        let end = match id3_chunk {
+            Some(chunk) if is_last(chunk) => chunk.offset,
+            Some(chunk) => {
+                remove_chunk(filepath, chunk, chunks.last().unwrap().end())?;
+                chunks.last().unwrap().end() - chunk.end() + chunk.offset
+            }
+            None => chunks.last().map_or(FIRST_CHUNK_OFFSET, Chunk::end),
+        };
+
+        let mut file = OpenOptions::new().write(true).open(filepath)?;
+        file.seek(SeekFrom::Start(end))?;
+        file.write_all(ID3_CHUNK_ID)?;
+        file.write_all(&(id3_data.len() as u64).to_be_bytes())?;
+        file.write_all(&id3_data)?;
+        if id3_data.len() % 2 == 1 {
+            file.write_all(&[0])?;
+        }
+        let file_size = file.stream_position()?;
+        file.set_len(file_size)?;
+
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&(file_size - CHUNK_HEADER_SIZE).to_be_bytes())?;
+
+        Ok(())
+    }
+
+    fn validate(&self, tags: &Tags) -> Result<()> {
+        validate_id3(tags)
+    }
+}
+
+/// FRM8チャンク直下のチャンク
+struct Chunk {
+    id: [u8; 4],
+    /// チャンクヘッダーの位置
+    offset: u64,
+    /// ヘッダーとパディングを含まないデータの長さ
+    size: u64,
+}
+
+impl Chunk {
+    /// パディングを含むチャンクの終わりの位置を返す。
+    fn end(&self) -> u64 {
+        self.offset + CHUNK_HEADER_SIZE + self.size + self.size % 2
+    }
+
+    fn read_data(&self, file: &mut File) -> Result<Vec<u8>> {
+        let mut data = vec![0; self.size as usize];
+        file.seek(SeekFrom::Start(self.offset + CHUNK_HEADER_SIZE))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// FRM8チャンク直下のチャンクを一覧にする。
+fn read_chunks(file: &mut File) -> Result<Vec<Chunk>> {
+    let mut header = [0; FIRST_CHUNK_OFFSET as usize];
+    if file.read_exact(&mut header).is_err()
+        || &header[..4] != FORM_CHUNK_ID
+        || &header[12..] != FORM_TYPE
+    {
+        Err(InvalidDffError::INSTANCE("DSDIFFファイルではありません"))?
+    }
+    let form_end = CHUNK_HEADER_SIZE + u64::from_be_bytes(header[4..12].try_into().unwrap());
+    if form_end > file.metadata()?.len() {
+        Err(InvalidDffError::INSTANCE(
+            "FRM8チャンクが途中で切れています",
+        ))?
+    }
+
+    let mut chunks = vec![];
+    let mut offset = FIRST_CHUNK_OFFSET;
+    while offset + CHUNK_HEADER_SIZE <= form_end {
+        let mut chunk_header = [0; CHUNK_HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut chunk_header)?;
+        let chunk = Chunk {
+            id: chunk_header[..4].try_into().unwrap(),
+            offset,
+            size: u64::from_be_bytes(chunk_header[4..].try_into().unwrap()),
+        };
+        if chunk.offset + CHUNK_HEADER_SIZE + chunk.size > form_end {
+            Err(InvalidDffError::INSTANCE("チャンクが途中で切れています"))?
+        }
+        offset = chunk.end();
+        chunks.push(chunk);
+    }
+
+    Ok(chunks)
+}
+
+fn find_chunk<'a>(chunks: &'a [Chunk], id: &[u8; 4]) -> Option<&'a Chunk> {
+    chunks.iter().find(|chunk| &chunk.id == id)
+}
+
+/// DIINチャンクの中のチャンクを(ID、データ)の一覧にする。
+fn parse_chunks(data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut chunks = vec![];
+    let mut rest = data;
+    while rest.len() >= CHUNK_HEADER_SIZE as usize {
+        let id = rest[..4].try_into().unwrap();
+        let size = u64::from_be_bytes(rest[4..12].try_into().unwrap()) as usize;
+        rest = &rest[CHUNK_HEADER_SIZE as usize..];
+        if size > rest.len() {
+            Err(InvalidDffError::INSTANCE(
+                "DIINチャンクが途中で切れています",
+            ))?
+        }
+        chunks.push((id, &rest[..size]));
+        rest = &rest[(size + size % 2).min(rest.len())..];
+    }
+
+    Ok(chunks)
+}
+
+/// DITI、DIARチャンクの文字列を返す。
+///
+/// 文字数(4バイト)の後に文字列が続く。文字コードの規定がないのでUTF-8として読む。
+fn find_text(chunks: &[([u8; 4], &[u8])], id: &[u8; 4]) -> Option<String> {
+    let (_, data) = chunks.iter().find(|(chunk_id, _)| chunk_id == id)?;
+    let count = u32::from_be_bytes(data.get(..4)?.try_into().unwrap()) as usize;
+    let text = data.get(4..4 + count)?;
+    let text = String::from_utf8_lossy(text)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// 末尾以外にあるチャンクを、後ろのチャンクを詰めて取り除く。
+fn remove_chunk(filepath: &Path, chunk: &Chunk, end: u64) -> Result<()> {
+    replace_file(filepath, |temp_file| {
+        let mut source = File::open(filepath)?;
+        io::copy(&mut (&mut source).take(chunk.offset), temp_file)?;
+        source.seek(SeekFrom::Start(chunk.end()))?;
+        io::copy(&mut source.take(end - chunk.end()), temp_file)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u64).to_be_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn dff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = [FORM_TYPE.to_vec(), chunks.concat()].concat();
+        [
+            FORM_CHUNK_ID.to_vec(),
+            (body.len() as u64).to_be_bytes().to_vec(),
+            body,
+        ]
+        .concat()
+    }
+
+    fn tags_with_title(title: &str) -> Tags {
+        let mut tags = Tags::new();
+        tags.set_title(Some(title.to_string()));
+        tags
+    }
+
+    fn chunk_ids(filepath: &Path) -> Vec<[u8; 4]> {
+        read_chunks(&mut File::open(filepath).unwrap())
+            .unwrap()
+            .iter()
+            .map(|chunk| chunk.id)
+            .collect()
+    }
+
+    fn form_size(filepath: &Path) -> u64 {
+        let contents = fs::read(filepath).unwrap();
+        u64::from_be_bytes(contents[4..12].try_into().unwrap())
+    }
+
+    #[test]
+    fn save_moves_non_trailing_id3_chunk_to_end() {
+        let mut id3_data = vec![];
+        build_id3(&tags_with_title("Old"))
+            .unwrap()
+            .write_to(&mut id3_data, Version::Id3v24)
+            .unwrap();
+        let sound_data = [0x69; 101];
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            dff(&[
+                chunk(b"FVER", &[1, 5, 0, 0]),
+                chunk(ID3_CHUNK_ID, &id3_data),
+                chunk(b"DSD ", &sound_data),
+            ]),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(file.path(), fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        DffIOImpl
+            .save(file.path(), &tags_with_title("New"))
+            .unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(file.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+
+        assert_eq!(
+            chunk_ids(file.path()),
+            vec![*b"FVER", *b"DSD ", *ID3_CHUNK_ID]
+        );
+        let contents = fs::read(file.path()).unwrap();
+        assert_eq!(form_size(file.path()), contents.len() as u64 - 12);
+        // 音声データとパディングは移動しても変わらない
+        let chunks = read_chunks(&mut File::open(file.path()).unwrap()).unwrap();
+        assert_eq!(
+            chunks[1]
+                .read_data(&mut File::open(file.path()).unwrap())
+                .unwrap(),
+            sound_data
+        );
+        assert_eq!(contents[chunks[1].end() as usize - 1], 0);
+        assert_eq!(DffIOImpl.load(file.path()).unwrap().title(), Some("New"));
+    }
+
+    #[test]
+    fn save_appends_id3_chunk_and_fixes_form_size() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            dff(&[chunk(b"FVER", &[1, 5, 0, 0]), chunk(b"DSD ", &[0x69; 8])]),
+        )
+        .unwrap();
+
+        DffIOImpl
+            .save(file.path(), &tags_with_title("First"))
+            .unwrap();
+        let first_len = fs::metadata(file.path()).unwrap().len();
+        assert_eq!(form_size(file.path()), first_len - 12);
+
+        // 末尾のID3チャンクは置き換えるだけで、短くなればファイルも切り詰める
+        DffIOImpl.save(file.path(), &Tags::new()).unwrap();
+        let second_len = fs::metadata(file.path()).unwrap().len();
+        assert!(second_len < first_len);
+        assert_eq!(form_size(file.path()), second_len - 12);
+        assert_eq!(
+            chunk_ids(file.path()),
+            vec![*b"FVER", *b"DSD ", *ID3_CHUNK_ID]
+        );
+        assert_eq!(DffIOImpl.load(file.path()).unwrap().title(), None);
+    }
+}
@@ -1,12 +1,30 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
 use dsf::DsfFile;
+use id3::Version;
 
-use crate::tags::id3_common::load_id3;
-use crate::tags::{TagIO, Tags};
+use crate::tags::file_common::replace_file;
+use crate::tags::id3_common::{build_id3, load_id3, validate_id3};
+use crate::tags::{InvalidDsfError, TagIO, Tags};
 use anyhow::Result;
 
-/// ID3タグIO実装
+/// DSDチャンクの総ファイルサイズの位置
+const FILE_SIZE_OFFSET: u64 = 12;
+
+/// DSDチャンクの長さ(fmtチャンクの位置)
+const FMT_CHUNK_OFFSET: u64 = 28;
+
+/// チャンクヘッダー(IDとサイズ)の長さ
+const CHUNK_HEADER_SIZE: u64 = 12;
+
+/// dataチャンクのID
+const DATA_CHUNK_ID: &[u8; 4] = b"data";
+
+/// DSFタグIO実装
+///
+/// DSFはファイル末尾のID3v2タグにタグ情報を持つ。
 pub struct DsfIOImpl;
 
 impl TagIO for DsfIOImpl {
@@ -20,7 +38,191 @@ impl TagIO for DsfIOImpl {
         load_id3(file_tags)
     }
 
-    fn save(&self, _: &Path, _: &Tags) -> Result<()> {
-        panic!("dsfクレートがまだ書き込みに対応していない");
+    /// dataチャンクの後のID3v2タグを書き換え、DSDチャンクの総ファイルサイズとメタデータの位置を更新する。
+    ///
+    /// dataチャンクの位置はfmtチャンクのサイズから求める。
+    /// 途中で失敗しても元のファイルが壊れないように、一時ファイルに書き出してから置き換える。
+    fn save(&self, filepath: &Path, tags: &Tags) -> Result<()> {
+        let mut id3_data = vec![];
+        build_id3(tags)?.write_to(&mut id3_data, Version::Id3v24)?;
+
+        let metadata_offset = find_metadata_offset(&mut File::open(filepath)?)?;
+        let file_size = metadata_offset + id3_data.len() as u64;
+
+        replace_file(filepath, |temp_file| {
+            let mut source = File::open(filepath)?;
+            io::copy(&mut (&mut source).take(metadata_offset), temp_file)?;
+            temp_file.write_all(&id3_data)?;
+
+            temp_file.seek(SeekFrom::Start(FILE_SIZE_OFFSET))?;
+            temp_file.write_all(&file_size.to_le_bytes())?;
+            temp_file.write_all(&metadata_offset.to_le_bytes())
+        })
+    }
+
+    fn validate(&self, tags: &Tags) -> Result<()> {
+        validate_id3(tags)
+    }
+}
+
+/// fmtチャンクとdataチャンクのサイズから、dataチャンクの後のメタデータの位置を求める。
+fn find_metadata_offset(file: &mut File) -> Result<u64> {
+    let file_len = file.metadata()?.len();
+    let mut read_u64 = |offset: u64| -> Result<u64> {
+        let mut bytes = [0; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    };
+
+    let data_chunk_offset = FMT_CHUNK_OFFSET.saturating_add(read_u64(FMT_CHUNK_OFFSET + 4)?);
+    if data_chunk_offset.saturating_add(CHUNK_HEADER_SIZE) > file_len {
+        Err(InvalidDsfError::INSTANCE(
+            "DSFファイルのfmtチャンクが途中で切れています",
+        ))?
+    }
+    // dataチャンクのサイズはヘッダーを含む
+    let data_chunk_size = read_u64(data_chunk_offset + 4)?;
+
+    let mut id = [0; 4];
+    file.seek(SeekFrom::Start(data_chunk_offset))?;
+    file.read_exact(&mut id)?;
+    if &id != DATA_CHUNK_ID {
+        Err(InvalidDsfError::INSTANCE(
+            "DSFファイルのdataチャンクがありません",
+        ))?
+    }
+
+    let metadata_offset = data_chunk_offset.saturating_add(data_chunk_size);
+    if metadata_offset > file_len {
+        Err(InvalidDsfError::INSTANCE(
+            "DSFファイルのdataチャンクが途中で切れています",
+        ))?
+    }
+
+    Ok(metadata_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+
+    use id3::{Tag, TagLike};
+
+    use super::*;
+
+    /// dataチャンクの音声データ
+    const SOUND_DATA: [u8; 16] = [0x69; 16];
+
+    /// fmtチャンクのサイズを指定してDSFファイルを作成する。
+    fn dsf(fmt_chunk_size: u64, id3_data: Option<&[u8]>) -> Vec<u8> {
+        let mut fmt_chunk = b"fmt ".to_vec();
+        fmt_chunk.extend(fmt_chunk_size.to_le_bytes());
+        for value in [1u32, 0, 2, 2, 2_822_400, 1] {
+            fmt_chunk.extend(value.to_le_bytes());
+        }
+        fmt_chunk.extend((SOUND_DATA.len() as u64 * 4).to_le_bytes());
+        fmt_chunk.extend(4096u32.to_le_bytes());
+        fmt_chunk.extend(0u32.to_le_bytes());
+        fmt_chunk.resize(fmt_chunk_size as usize, 0);
+
+        let mut data_chunk = DATA_CHUNK_ID.to_vec();
+        data_chunk.extend((CHUNK_HEADER_SIZE + SOUND_DATA.len() as u64).to_le_bytes());
+        data_chunk.extend(SOUND_DATA);
+
+        let metadata_offset = FMT_CHUNK_OFFSET + fmt_chunk_size + data_chunk.len() as u64;
+        let id3_data = id3_data.unwrap_or_default();
+        let file_size = metadata_offset + id3_data.len() as u64;
+        let mut contents = b"DSD ".to_vec();
+        contents.extend(FMT_CHUNK_OFFSET.to_le_bytes());
+        contents.extend(file_size.to_le_bytes());
+        contents.extend(
+            if id3_data.is_empty() {
+                0
+            } else {
+                metadata_offset
+            }
+            .to_le_bytes(),
+        );
+        contents.extend(fmt_chunk);
+        contents.extend(data_chunk);
+        contents.extend(id3_data);
+        contents
+    }
+
+    fn tags_with_title(title: &str) -> Tags {
+        let mut tags = Tags::new();
+        tags.set_title(Some(title.to_string()));
+        tags
+    }
+
+    /// DSDチャンクの総ファイルサイズとメタデータの位置を返す。
+    fn header_fields(contents: &[u8]) -> (u64, u64) {
+        (
+            u64::from_le_bytes(contents[12..20].try_into().unwrap()),
+            u64::from_le_bytes(contents[20..28].try_into().unwrap()),
+        )
+    }
+
+    #[test]
+    fn save_updates_file_size_and_metadata_offset() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), dsf(52, None)).unwrap();
+        let metadata_offset = FMT_CHUNK_OFFSET + 52 + CHUNK_HEADER_SIZE + SOUND_DATA.len() as u64;
+
+        // ID3v2タグがないファイルにはdataチャンクの後に追加する
+        DsfIOImpl
+            .save(file.path(), &tags_with_title("A fairly long first title"))
+            .unwrap();
+        let contents = fs::read(file.path()).unwrap();
+        assert_eq!(
+            header_fields(&contents),
+            (contents.len() as u64, metadata_offset)
+        );
+        assert_eq!(
+            DsfIOImpl.load(file.path()).unwrap().title(),
+            Some("A fairly long first title")
+        );
+        let first_len = contents.len();
+
+        // 既存のID3v2タグは置き換え、短くなればファイルも切り詰める
+        DsfIOImpl
+            .save(file.path(), &tags_with_title("Short"))
+            .unwrap();
+        let contents = fs::read(file.path()).unwrap();
+        assert!(contents.len() < first_len);
+        assert_eq!(
+            header_fields(&contents),
+            (contents.len() as u64, metadata_offset)
+        );
+        assert_eq!(DsfIOImpl.load(file.path()).unwrap().title(), Some("Short"));
+        let data_start = (metadata_offset - SOUND_DATA.len() as u64) as usize;
+        assert_eq!(&contents[data_start..metadata_offset as usize], SOUND_DATA);
+    }
+
+    #[test]
+    fn save_finds_data_chunk_from_fmt_chunk_size() {
+        let mut id3_data = vec![];
+        build_id3(&tags_with_title("Old"))
+            .unwrap()
+            .write_to(&mut id3_data, Version::Id3v24)
+            .unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), dsf(60, Some(&id3_data))).unwrap();
+
+        DsfIOImpl
+            .save(file.path(), &tags_with_title("New"))
+            .unwrap();
+
+        let contents = fs::read(file.path()).unwrap();
+        let metadata_offset = FMT_CHUNK_OFFSET + 60 + CHUNK_HEADER_SIZE + SOUND_DATA.len() as u64;
+        assert_eq!(
+            header_fields(&contents),
+            (contents.len() as u64, metadata_offset)
+        );
+        let file_tags =
+            Tag::read_from2(Cursor::new(&contents[metadata_offset as usize..])).unwrap();
+        assert_eq!(file_tags.title(), Some("New"));
     }
 }
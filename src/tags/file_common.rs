@@ -0,0 +1,29 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use tempfile::NamedTempFile;
+
+use anyhow::Result;
+
+/// ファイルの内容を書き出し直して置き換える。
+///
+/// 途中で失敗しても元のファイルが壊れないように、同じフォルダの一時ファイルに書き出してから置き換える。
+/// 一時ファイル名は重複しないので、拡張子だけが異なるファイルを並列に保存しても互いに上書きしない。
+/// 一時ファイルには元のファイルのパーミッションを引き継ぎ、失敗した場合は削除する。
+pub fn replace_file<T>(
+    filepath: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> Result<T> {
+    let folder = match filepath.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+    let mut temp_file = NamedTempFile::new_in(folder)?;
+
+    let value = write(temp_file.as_file_mut())?;
+    fs::set_permissions(temp_file.path(), fs::metadata(filepath)?.permissions())?;
+    temp_file.persist(filepath)?;
+
+    Ok(value)
+}
@@ -1,9 +1,32 @@
-use id3::frame::PictureType;
-use id3::{Tag, TagLike};
+use std::str::FromStr;
 
-use crate::tags::{Image, ImageFormat, Tags};
+use id3::frame::{Comment, ExtendedText, Picture, PictureType};
+use id3::{Tag, TagLike, Timestamp};
+
+use crate::model::Role;
+use crate::tags::{Image, ImageFormat, InvalidTagValueError, Tags};
 use anyhow::Result;
 
+/// カタログ番号を保存するTXXXフレームの説明
+const CATALOG_NUMBER_DESCRIPTION: &str = "CATALOGNUMBER";
+
+/// バーコードを保存するTXXXフレームの説明
+const BARCODE_DESCRIPTION: &str = "BARCODE";
+
+/// レコードの面を含むトラックの位置を保存するTXXXフレームの説明
+const TRACK_POSITION_DESCRIPTION: &str = "TRACKPOSITION";
+
+/// コメントを保存するCOMMフレームの言語
+const COMMENT_LANG: &str = "eng";
+
+/// 編曲者を保存するTIPLフレームの役割
+const ARRANGER_INVOLVEMENT: &str = "arranger";
+
+/// 演奏者を保存するTMCLフレームの楽器
+///
+/// 楽器は扱わないので、読み込むときはどの楽器の演奏者も演奏者とする。
+const PERFORMER_INSTRUMENT: &str = "performer";
+
 pub fn load_id3(file_tags: &Tag) -> Result<Tags> {
     let mut tags = Tags::new();
 
@@ -15,10 +38,21 @@ pub fn load_id3(file_tags: &Tag) -> Result<Tags> {
             tags.set_release_date(Some(release_date));
         }
     }
+    tags.set_genre(file_tags.genre_parsed().map(String::from));
+    tags.set_label(file_tags.text_for_frame_id("TPUB").map(String::from));
+    tags.set_catalog_number(get_extended_text(file_tags, CATALOG_NUMBER_DESCRIPTION));
+    tags.set_barcode(get_extended_text(file_tags, BARCODE_DESCRIPTION));
+    tags.set_compilation(file_tags.text_for_frame_id("TCMP") == Some("1"));
+    tags.set_comment(get_comment(file_tags));
+    if let Some(ts) = file_tags.original_date_released() {
+        tags.set_original_release_date(Some(ts.to_string()));
+    }
     tags.set_number_of_discs(file_tags.total_discs().map(|n| n as usize));
     tags.set_disc_number(file_tags.disc().map(|n| n as usize));
+    tags.set_disc_title(file_tags.text_for_frame_id("TSST").map(String::from));
     tags.set_number_of_tracks(file_tags.total_tracks().map(|n| n as usize));
     tags.set_track_number(file_tags.track().map(|n| n as usize));
+    tags.set_track_position(get_extended_text(file_tags, TRACK_POSITION_DESCRIPTION));
     tags.set_title(file_tags.title().map(String::from));
 
     if let Some(artists) = file_tags.artist() {
@@ -31,6 +65,21 @@ pub fn load_id3(file_tags: &Tag) -> Result<Tags> {
         }
     }
 
+    for name in get_text_values(file_tags, "TEXT") {
+        tags.add_credit(Role::Lyricist, name);
+    }
+    for name in get_text_values(file_tags, "TCOM") {
+        tags.add_credit(Role::Composer, name);
+    }
+    for (involvement, name) in get_text_pairs(file_tags, "TIPL") {
+        if involvement.eq_ignore_ascii_case(ARRANGER_INVOLVEMENT) {
+            tags.add_credit(Role::Arranger, name);
+        }
+    }
+    for (_, name) in get_text_pairs(file_tags, "TMCL") {
+        tags.add_credit(Role::Performer, name);
+    }
+
     for picture in file_tags.pictures() {
         if picture.picture_type == PictureType::CoverFront {
             let format = ImageFormat::from_data(&picture.data)?;
@@ -43,3 +92,204 @@ pub fn load_id3(file_tags: &Tag) -> Result<Tags> {
 
     Ok(tags)
 }
+
+/// タグ情報からID3タグを作成する。
+///
+/// MP3、WAV、DSFで同じフレームに設定する。
+pub fn build_id3(tags: &Tags) -> Result<Tag> {
+    let mut file_tags = Tag::new();
+
+    if let Some(album) = tags.album() {
+        file_tags.set_album(album);
+    }
+    if let Some(album_artist) = tags.album_artist() {
+        file_tags.set_album_artist(album_artist);
+    }
+    if let Some(release_date) = tags.release_date() {
+        let release_date = Timestamp::from_str(release_date)?;
+        file_tags.set_date_released(release_date);
+    }
+    save_id3_album_details(&mut file_tags, tags)?;
+    if let Some(number_of_discs) = tags.number_of_discs() {
+        file_tags.set_total_discs(number_of_discs as u32);
+    }
+    if let Some(disc_number) = tags.disc_number() {
+        file_tags.set_disc(disc_number as u32);
+    }
+    if let Some(number_of_tracks) = tags.number_of_tracks() {
+        file_tags.set_total_tracks(number_of_tracks as u32);
+    }
+    if let Some(track_number) = tags.track_number() {
+        file_tags.set_track(track_number as u32);
+    }
+    if let Some(title) = tags.title() {
+        file_tags.set_title(title);
+    }
+
+    let mut artists = String::new();
+    for artist in tags.artists() {
+        if artists.len() > 0 {
+            artists.push(';');
+        }
+        artists.push_str(artist);
+    }
+    if artists.len() > 0 {
+        file_tags.set_artist(artists);
+    }
+    save_id3_track_details(&mut file_tags, tags);
+
+    if let Some(image) = tags.art_work() {
+        let mime_type = image.format.mime().to_string();
+        let data = image.data.to_owned();
+        file_tags.add_frame(Picture {
+            mime_type,
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data,
+        });
+    }
+
+    Ok(file_tags)
+}
+
+/// アルバム情報の追加の項目をID3タグに設定する。
+pub fn save_id3_album_details(file_tags: &mut Tag, tags: &Tags) -> Result<()> {
+    if let Some(genre) = tags.genre() {
+        file_tags.set_genre(genre);
+    }
+    if let Some(label) = tags.label() {
+        file_tags.set_text("TPUB", label);
+    }
+    if let Some(catalog_number) = tags.catalog_number() {
+        file_tags.add_frame(ExtendedText {
+            description: CATALOG_NUMBER_DESCRIPTION.to_string(),
+            value: catalog_number.to_string(),
+        });
+    }
+    if let Some(barcode) = tags.barcode() {
+        file_tags.add_frame(ExtendedText {
+            description: BARCODE_DESCRIPTION.to_string(),
+            value: barcode.to_string(),
+        });
+    }
+    if tags.compilation() {
+        file_tags.set_text("TCMP", "1");
+    }
+    if let Some(comment) = tags.comment() {
+        file_tags.add_frame(Comment {
+            lang: COMMENT_LANG.to_string(),
+            description: String::new(),
+            text: comment.to_string(),
+        });
+    }
+    if let Some(original_release_date) = tags.original_release_date() {
+        let original_release_date = Timestamp::from_str(original_release_date)?;
+        file_tags.set_original_date_released(original_release_date);
+    }
+
+    Ok(())
+}
+
+/// ディスクとトラック情報の追加の項目とクレジットをID3タグに設定する。
+pub fn save_id3_track_details(file_tags: &mut Tag, tags: &Tags) {
+    if let Some(disc_title) = tags.disc_title() {
+        file_tags.set_text("TSST", disc_title);
+    }
+    if let Some(track_position) = tags.track_position() {
+        file_tags.add_frame(ExtendedText {
+            description: TRACK_POSITION_DESCRIPTION.to_string(),
+            value: track_position.to_string(),
+        });
+    }
+    if !tags.credits(Role::Lyricist).is_empty() {
+        file_tags.set_text_values("TEXT", tags.credits(Role::Lyricist));
+    }
+    if !tags.credits(Role::Composer).is_empty() {
+        file_tags.set_text_values("TCOM", tags.credits(Role::Composer));
+    }
+    if !tags.credits(Role::Arranger).is_empty() {
+        let pairs = tags
+            .credits(Role::Arranger)
+            .iter()
+            .flat_map(|name| [ARRANGER_INVOLVEMENT, name.as_str()]);
+        file_tags.set_text_values("TIPL", pairs);
+    }
+    if !tags.credits(Role::Performer).is_empty() {
+        let pairs = tags
+            .credits(Role::Performer)
+            .iter()
+            .flat_map(|name| [PERFORMER_INSTRUMENT, name.as_str()]);
+        file_tags.set_text_values("TMCL", pairs);
+    }
+}
+
+/// テキストフレームの値を\0区切りの複数の値として返す。
+fn get_text_values(file_tags: &Tag, id: &str) -> Vec<String> {
+    match file_tags.text_values_for_frame_id(id) {
+        Some(values) => values.into_iter().map(String::from).collect(),
+        None => vec![],
+    }
+}
+
+/// TIPLやTMCLのように役割と名前を交互に並べたテキストフレームの値を組にして返す。
+fn get_text_pairs(file_tags: &Tag, id: &str) -> Vec<(String, String)> {
+    let values = get_text_values(file_tags, id);
+    values
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect()
+}
+
+/// 指定された説明のTXXXフレームの値を返す。
+fn get_extended_text(file_tags: &Tag, description: &str) -> Option<String> {
+    file_tags
+        .extended_texts()
+        .find(|extended_text| extended_text.description == description)
+        .map(|extended_text| extended_text.value.clone())
+}
+
+/// 説明のないCOMMフレームを優先してコメントを返す。
+fn get_comment(file_tags: &Tag) -> Option<String> {
+    file_tags
+        .comments()
+        .find(|comment| comment.description.is_empty())
+        .or_else(|| file_tags.comments().next())
+        .map(|comment| comment.text.clone())
+}
+
+/// タグ情報がID3タグとして書き込めるかを検証する。
+pub fn validate_id3(tags: &Tags) -> Result<()> {
+    let dates = [
+        ("発売日", tags.release_date()),
+        ("オリジナル発売日", tags.original_release_date()),
+    ];
+    for (name, date) in dates {
+        if let Some(date) = date {
+            if Timestamp::from_str(date).is_err() {
+                Err(InvalidTagValueError::INSTANCE(format!(
+                    "{}をID3の日付に変換できません: {}",
+                    name, date
+                )))?
+            }
+        }
+    }
+
+    let numbers = [
+        ("総ディスク数", tags.number_of_discs()),
+        ("ディスク番号", tags.disc_number()),
+        ("総トラック数", tags.number_of_tracks()),
+        ("トラック番号", tags.track_number()),
+    ];
+    for (name, number) in numbers {
+        if let Some(number) = number {
+            if u32::try_from(number).is_err() {
+                Err(InvalidTagValueError::INSTANCE(format!(
+                    "{}がID3で扱える範囲を超えています: {}",
+                    name, number
+                )))?
+            }
+        }
+    }
+
+    Ok(())
+}
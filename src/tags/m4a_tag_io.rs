@@ -1,10 +1,35 @@
 use std::path::Path;
 
-use mp4ameta::{Img, ImgFmt, Tag};
+use mp4ameta::{Data, FreeformIdent, Img, ImgFmt, Tag};
 
-use crate::tags::{Image, ImageFormat, ImageFormatError, TagIO, Tags};
+use crate::model::Role;
+use crate::tags::{Image, ImageFormat, ImageFormatError, InvalidTagValueError, TagIO, Tags};
 use anyhow::Result;
 
+/// レーベルを保存するフリーフォームアトム
+const LABEL: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "LABEL");
+
+/// カタログ番号を保存するフリーフォームアトム
+const CATALOG_NUMBER: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "CATALOGNUMBER");
+
+/// バーコードを保存するフリーフォームアトム
+const BARCODE: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "BARCODE");
+
+/// ディスクタイトルを保存するフリーフォームアトム
+const DISC_SUBTITLE: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "DISCSUBTITLE");
+
+/// レコードの面を含むトラックの位置を保存するフリーフォームアトム
+const TRACK_POSITION: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "TRACKPOSITION");
+
+/// 編曲者を保存するフリーフォームアトム
+const ARRANGER: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "ARRANGER");
+
+/// 演奏者を保存するフリーフォームアトム
+const PERFORMER: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "PERFORMER");
+
+/// オリジナル発売日を保存するフリーフォームアトム
+const ORIGINAL_DATE: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "ORIGINALDATE");
+
 /// M4AタグIO実装
 pub struct M4AIOImpl;
 
@@ -17,15 +42,36 @@ impl TagIO for M4AIOImpl {
         tags.set_album(file_tags.take_album());
         tags.set_album_artist(file_tags.take_album_artist());
         tags.set_release_date(file_tags.take_year());
+        tags.set_genre(file_tags.take_genre());
+        tags.set_label(get_freeform(&file_tags, &LABEL));
+        tags.set_catalog_number(get_freeform(&file_tags, &CATALOG_NUMBER));
+        tags.set_barcode(get_freeform(&file_tags, &BARCODE));
+        tags.set_compilation(file_tags.compilation());
+        tags.set_comment(file_tags.take_comment());
+        tags.set_original_release_date(get_freeform(&file_tags, &ORIGINAL_DATE));
         tags.set_number_of_discs(file_tags.total_discs().map(|n| n as usize));
         tags.set_disc_number(file_tags.disc_number().map(|n| n as usize));
+        tags.set_disc_title(get_freeform(&file_tags, &DISC_SUBTITLE));
         tags.set_number_of_tracks(file_tags.total_tracks().map(|n| n as usize));
         tags.set_track_number(file_tags.track_number().map(|n| n as usize));
+        tags.set_track_position(get_freeform(&file_tags, &TRACK_POSITION));
         tags.set_title(file_tags.take_title());
 
         for artist in file_tags.take_artists() {
             tags.add_artist(artist);
         }
+        for name in file_tags.take_lyricists() {
+            tags.add_credit(Role::Lyricist, name);
+        }
+        for name in file_tags.take_composers() {
+            tags.add_credit(Role::Composer, name);
+        }
+        for name in file_tags.strings_of(&ARRANGER) {
+            tags.add_credit(Role::Arranger, name.to_string());
+        }
+        for name in file_tags.strings_of(&PERFORMER) {
+            tags.add_credit(Role::Performer, name.to_string());
+        }
 
         if let Some(art_work) = file_tags.take_artwork() {
             let format = to_common_image_format(&art_work.fmt)?;
@@ -52,22 +98,41 @@ impl TagIO for M4AIOImpl {
         if let Some(release_date) = tags.release_date() {
             file_tags.set_year(release_date);
         }
+        if let Some(genre) = tags.genre() {
+            file_tags.set_genre(genre);
+        }
+        set_freeform(&mut file_tags, LABEL, tags.label());
+        set_freeform(&mut file_tags, CATALOG_NUMBER, tags.catalog_number());
+        set_freeform(&mut file_tags, BARCODE, tags.barcode());
+        if tags.compilation() {
+            file_tags.set_compilation();
+        }
+        if let Some(comment) = tags.comment() {
+            file_tags.set_comment(comment);
+        }
+        set_freeform(&mut file_tags, ORIGINAL_DATE, tags.original_release_date());
         if let Some(number_of_discs) = tags.number_of_discs() {
             file_tags.set_total_discs(number_of_discs as u16);
         }
         if let Some(disc_number) = tags.disc_number() {
             file_tags.set_disc_number(disc_number as u16);
         }
+        set_freeform(&mut file_tags, DISC_SUBTITLE, tags.disc_title());
         if let Some(number_of_tracks) = tags.number_of_tracks() {
             file_tags.set_total_tracks(number_of_tracks as u16);
         }
         if let Some(track_number) = tags.track_number() {
             file_tags.set_track_number(track_number as u16);
         }
+        set_freeform(&mut file_tags, TRACK_POSITION, tags.track_position());
         if let Some(title) = tags.title() {
             file_tags.set_title(title);
         }
         file_tags.set_artists(tags.artists.clone());
+        file_tags.set_lyricists(tags.credits(Role::Lyricist).clone());
+        file_tags.set_composers(tags.credits(Role::Composer).clone());
+        set_freeform_values(&mut file_tags, ARRANGER, tags.credits(Role::Arranger));
+        set_freeform_values(&mut file_tags, PERFORMER, tags.credits(Role::Performer));
 
         if let Some(image) = tags.art_work() {
             let format = to_m4a_image_format(&image.format)?;
@@ -80,6 +145,44 @@ impl TagIO for M4AIOImpl {
 
         Ok(())
     }
+
+    fn validate(&self, tags: &Tags) -> Result<()> {
+        let numbers = [
+            ("総ディスク数", tags.number_of_discs()),
+            ("ディスク番号", tags.disc_number()),
+            ("総トラック数", tags.number_of_tracks()),
+            ("トラック番号", tags.track_number()),
+        ];
+        for (name, number) in numbers {
+            if let Some(number) = number {
+                if u16::try_from(number).is_err() {
+                    Err(InvalidTagValueError::INSTANCE(format!(
+                        "{}がM4Aで扱える範囲を超えています: {}",
+                        name, number
+                    )))?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn get_freeform(file_tags: &Tag, ident: &FreeformIdent) -> Option<String> {
+    file_tags.strings_of(ident).next().map(String::from)
+}
+
+fn set_freeform(file_tags: &mut Tag, ident: FreeformIdent<'static>, value: Option<&str>) {
+    if let Some(value) = value {
+        file_tags.set_data(ident, Data::Utf8(value.to_string()));
+    }
+}
+
+fn set_freeform_values(file_tags: &mut Tag, ident: FreeformIdent<'static>, values: &[String]) {
+    if !values.is_empty() {
+        let data = values.iter().map(|value| Data::Utf8(value.clone()));
+        file_tags.set_all_data(ident, data);
+    }
 }
 
 fn to_common_image_format(m4a_image_format: &ImgFmt) -> Result<ImageFormat> {
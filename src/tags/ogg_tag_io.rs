@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Write};
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use metaflac::block::{Block, Picture, VorbisComment};
+use metaflac::Tag;
+use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+
+use crate::tags::file_common::replace_file;
+use crate::tags::vorbis_common::{load_vorbis_comments, save_vorbis_comments};
+use crate::tags::{InvalidOggError, TagIO, Tags};
+use anyhow::Result;
+
+/// 画像を保存するVorbisコメントの項目名
+const PICTURE_ITEM_NAME: &str = "METADATA_BLOCK_PICTURE";
+
+/// Oggに格納された音声のコーデック
+#[derive(Clone, Copy)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    /// 最初のパケット(識別ヘッダー)からコーデックを判定する。
+    fn from_identification_header(data: &[u8]) -> Option<Codec> {
+        if data.starts_with(b"\x01vorbis") {
+            Some(Codec::Vorbis)
+        } else if data.starts_with(b"OpusHead") {
+            Some(Codec::Opus)
+        } else {
+            None
+        }
+    }
+
+    /// コメントヘッダーのVorbisコメントの前に付くバイト列を返す。
+    fn comment_header_prefix(&self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => b"\x03vorbis",
+            Codec::Opus => b"OpusTags",
+        }
+    }
+
+    /// コメントヘッダーのパケットからVorbisコメントを取り出す。
+    fn parse_comment_header(&self, data: &[u8]) -> Result<VorbisComment> {
+        match data.strip_prefix(self.comment_header_prefix()) {
+            Some(comments) => Ok(VorbisComment::from_bytes(comments)?),
+            None => Err(InvalidOggError::INSTANCE(
+                "Oggのコメントヘッダーがありません",
+            ))?,
+        }
+    }
+
+    /// Vorbisコメントからコメントヘッダーのパケットを作成する。
+    fn build_comment_header(&self, vorbis_comment: &VorbisComment) -> Vec<u8> {
+        let mut data = self.comment_header_prefix().to_vec();
+        data.extend(vorbis_comment.to_bytes());
+        if let Codec::Vorbis = self {
+            // Vorbisのコメントヘッダーはフレーミングビットで終わる
+            data.push(1);
+        }
+        data
+    }
+}
+
+/// Ogg Vorbis、OpusのタグIO実装
+///
+/// FLACと同じ項目名のVorbisコメントにタグ情報を持つ。
+/// アートワークはMETADATA_BLOCK_PICTUREにFLACの画像ブロックをBase64で符号化して持つ。
+pub struct OggIOImpl;
+
+impl TagIO for OggIOImpl {
+    fn load(&self, filepath: &Path) -> Result<Tags> {
+        let mut reader = PacketReader::new(BufReader::new(File::open(filepath)?));
+
+        let identification_header = match reader.read_packet()? {
+            Some(packet) => packet,
+            None => Err(InvalidOggError::INSTANCE("Oggのパケットがありません"))?,
+        };
+        let codec = match Codec::from_identification_header(&identification_header.data) {
+            Some(codec) => codec,
+            None => Err(InvalidOggError::INSTANCE(
+                "VorbisまたはOpus以外のOggには対応していません",
+            ))?,
+        };
+        let comment_header = loop {
+            match reader.read_packet()? {
+                Some(packet) if packet.stream_serial() == identification_header.stream_serial() => {
+                    break packet
+                }
+                Some(_) => continue,
+                None => Err(InvalidOggError::INSTANCE(
+                    "Oggのコメントヘッダーがありません",
+                ))?,
+            }
+        };
+
+        let mut vorbis_comment = codec.parse_comment_header(&comment_header.data)?;
+        let pictures = vorbis_comment.comments.remove(PICTURE_ITEM_NAME);
+
+        let mut file_tags = Tag::new();
+        file_tags.push_block(Block::VorbisComment(vorbis_comment));
+        for picture in pictures.unwrap_or_default() {
+            let picture = Picture::from_bytes(&STANDARD.decode(picture)?)?;
+            file_tags.push_block(Block::Picture(picture));
+        }
+
+        load_vorbis_comments(&file_tags)
+    }
+
+    /// コメントヘッダーを置き換えてOggページを作り直す。
+    ///
+    /// ほかのパケットはページの区切りとグラニュール位置を保ったまま書き直し、
+    /// ページ番号とCRCはページを書くときに計算し直す。
+    /// 書き直したファイルは一時ファイルを経由して置き換える。
+    fn save(&self, filepath: &Path, tags: &Tags) -> Result<()> {
+        let contents = fs::read(filepath)?;
+        let mut reader = PacketReader::new(Cursor::new(contents.as_slice()));
+        let mut writer = PacketWriter::new(Vec::with_capacity(contents.len()));
+
+        // 論理ストリームごとのコーデックと読み込んだパケット数
+        let mut streams: HashMap<u32, (Option<Codec>, usize)> = HashMap::new();
+        while let Some(packet) = reader.read_packet()? {
+            let serial = packet.stream_serial();
+            let (codec, packet_count) = streams.entry(serial).or_insert((None, 0));
+            if *packet_count == 0 {
+                *codec = Codec::from_identification_header(&packet.data);
+            }
+            *packet_count += 1;
+
+            let end_info = if packet.last_in_stream() {
+                PacketWriteEndInfo::EndStream
+            } else if packet.last_in_page() {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            let absgp = packet.absgp_page();
+            let data = match codec {
+                Some(codec) if *packet_count == 2 => {
+                    let vendor_string = codec.parse_comment_header(&packet.data)?.vendor_string;
+                    codec.build_comment_header(&build_vorbis_comment(vendor_string, tags))
+                }
+                _ => packet.data,
+            };
+            writer.write_packet(data, serial, end_info, absgp)?;
+        }
+
+        if !streams.values().any(|(codec, _)| codec.is_some()) {
+            Err(InvalidOggError::INSTANCE(
+                "VorbisまたはOpus以外のOggには対応していません",
+            ))?
+        }
+
+        let contents = writer.into_inner();
+        replace_file(filepath, |file| file.write_all(&contents))?;
+
+        Ok(())
+    }
+}
+
+/// タグ情報からアートワークを含むVorbisコメントを作成する。
+fn build_vorbis_comment(vendor_string: String, tags: &Tags) -> VorbisComment {
+    let mut file_tags = Tag::new();
+    save_vorbis_comments(&mut file_tags, tags);
+
+    let pictures: Vec<String> = file_tags
+        .pictures()
+        .map(|picture| STANDARD.encode(picture.to_bytes()))
+        .collect();
+
+    let mut vorbis_comment = VorbisComment::new();
+    vorbis_comment.vendor_string = vendor_string;
+    if let Some(comments) = file_tags.vorbis_comments() {
+        vorbis_comment.comments = comments.comments.clone();
+    }
+    if !pictures.is_empty() {
+        vorbis_comment.set(PICTURE_ITEM_NAME, pictures);
+    }
+
+    vorbis_comment
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tags::{Image, ImageFormat};
+
+    use super::*;
+
+    /// コメントヘッダーが複数のページにまたがる大きさのアートワーク
+    fn art_work() -> Image {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend((0..100_000).map(|i| i as u8));
+        Image::new(ImageFormat::PNG, data)
+    }
+
+    fn comment_header(codec: Codec, title: &str) -> Vec<u8> {
+        let mut vorbis_comment = VorbisComment::new();
+        vorbis_comment.vendor_string = "test vendor".to_string();
+        vorbis_comment.set("TITLE", vec![title]);
+        codec.build_comment_header(&vorbis_comment)
+    }
+
+    /// 識別ヘッダー、コメントヘッダー、残りのパケットからOggストリームを作成する。
+    fn ogg_stream(
+        identification_header: &[u8],
+        comment_header: Vec<u8>,
+        rest: &[&[u8]],
+    ) -> Vec<u8> {
+        let serial = 0x1234;
+        let mut writer = PacketWriter::new(vec![]);
+        writer
+            .write_packet(
+                identification_header.to_vec(),
+                serial,
+                PacketWriteEndInfo::EndPage,
+                0,
+            )
+            .unwrap();
+        writer
+            .write_packet(comment_header, serial, PacketWriteEndInfo::NormalPacket, 0)
+            .unwrap();
+        for (i, packet) in rest.iter().enumerate() {
+            let end_info = if i == rest.len() - 1 {
+                PacketWriteEndInfo::EndStream
+            } else {
+                PacketWriteEndInfo::EndPage
+            };
+            writer
+                .write_packet(packet.to_vec(), serial, end_info, i as u64 * 960)
+                .unwrap();
+        }
+        writer.into_inner()
+    }
+
+    /// パケットを(データ、ページの最後のパケットのグラニュール位置)の一覧にする。
+    ///
+    /// 読み込みでページのCRCも検証される。
+    fn read_packets(contents: &[u8]) -> Vec<(Vec<u8>, Option<u64>)> {
+        let mut reader = PacketReader::new(Cursor::new(contents));
+        let mut packets = vec![];
+        while let Some(packet) = reader.read_packet().unwrap() {
+            let absgp = packet.last_in_page().then(|| packet.absgp_page());
+            packets.push((packet.data, absgp));
+        }
+        packets
+    }
+
+    /// ページごとのシリアル番号とページ番号を一覧にする。
+    fn page_sequences(contents: &[u8]) -> Vec<(u32, u32)> {
+        let mut sequences = vec![];
+        let mut rest = contents;
+        while !rest.is_empty() {
+            assert_eq!(&rest[..4], b"OggS");
+            let serial = u32::from_le_bytes(rest[14..18].try_into().unwrap());
+            let sequence = u32::from_le_bytes(rest[18..22].try_into().unwrap());
+            let segments = rest[26] as usize;
+            let body: usize = rest[27..27 + segments].iter().map(|&n| n as usize).sum();
+            sequences.push((serial, sequence));
+            rest = &rest[27 + segments + body..];
+        }
+        sequences
+    }
+
+    fn save_and_reload(codec: Codec, contents: Vec<u8>) -> Vec<u8> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), &contents).unwrap();
+
+        let mut tags = Tags::new();
+        tags.set_title(Some("New".to_string()));
+        tags.set_art_work(Some(art_work()));
+        OggIOImpl.save(file.path(), &tags).unwrap();
+
+        let saved = fs::read(file.path()).unwrap();
+        let loaded = OggIOImpl.load(file.path()).unwrap();
+        assert_eq!(loaded.title(), Some("New"));
+        assert_eq!(loaded.art_work().unwrap().data(), art_work().data());
+
+        // ページ番号は0から振り直され、CRCも正しい
+        let sequences = page_sequences(&saved);
+        assert!(sequences.len() > page_sequences(&contents).len());
+        for (i, (serial, sequence)) in sequences.into_iter().enumerate() {
+            assert_eq!((serial, sequence), (0x1234, i as u32));
+        }
+
+        // コメントヘッダー以外のパケットとグラニュール位置は変わらない
+        let original_packets = read_packets(&contents);
+        let saved_packets = read_packets(&saved);
+        assert_eq!(saved_packets.len(), original_packets.len());
+        for (i, (original, saved)) in original_packets
+            .iter()
+            .zip(saved_packets.iter())
+            .enumerate()
+        {
+            if i == 1 {
+                let vorbis_comment = codec.parse_comment_header(&saved.0).unwrap();
+                assert_eq!(vorbis_comment.vendor_string, "test vendor");
+            } else {
+                assert_eq!(original.0, saved.0);
+            }
+            assert_eq!(original.1, saved.1);
+        }
+
+        saved_packets.into_iter().nth(1).unwrap().0
+    }
+
+    #[test]
+    fn save_vorbis_stream() {
+        let contents = ogg_stream(
+            b"\x01vorbis\0\0\0\0\x02\x44\xac\0\0",
+            comment_header(Codec::Vorbis, "Old"),
+            &[b"\x05vorbis setup", b"audio 1", b"audio 2"],
+        );
+
+        let comment_header = save_and_reload(Codec::Vorbis, contents);
+
+        assert!(comment_header.starts_with(b"\x03vorbis"));
+        // フレーミングビットで終わる
+        assert_eq!(comment_header.last(), Some(&1));
+    }
+
+    #[test]
+    fn save_opus_stream() {
+        let contents = ogg_stream(
+            b"OpusHead\x01\x02\x38\x01\x80\xbb\0\0\0\0\0",
+            comment_header(Codec::Opus, "Old"),
+            &[b"audio 1", b"audio 2"],
+        );
+
+        let comment_header = save_and_reload(Codec::Opus, contents);
+
+        assert!(comment_header.starts_with(b"OpusTags"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::str::FromStr;
+
+use metaflac::block::PictureType;
+use metaflac::Tag;
+
+use crate::model::Role;
+use crate::tags::{Image, ImageFormat, InvalidTagValueError, Tags};
+use anyhow::Result;
+
+/// クレジットの役割ごとのVorbisコメントの項目名
+const CREDIT_ITEM_NAMES: [(Role, &str); 4] = [
+    (Role::Lyricist, "LYRICIST"),
+    (Role::Composer, "COMPOSER"),
+    (Role::Arranger, "ARRANGER"),
+    (Role::Performer, "PERFORMER"),
+];
+
+/// Vorbisコメントと画像からタグ情報を作成する。
+///
+/// FLACとOggで同じ項目名を使う。
+pub fn load_vorbis_comments(file_tags: &Tag) -> Result<Tags> {
+    let mut tags = Tags::new();
+
+    tags.set_album(get_string(file_tags, "ALBUM").map(String::from));
+    tags.set_album_artist(get_string(file_tags, "ALBUMARTIST").map(String::from));
+    tags.set_release_date(get_string(file_tags, "DATE").map(String::from));
+    tags.set_genre(get_string(file_tags, "GENRE").map(String::from));
+    tags.set_label(get_string(file_tags, "LABEL").map(String::from));
+    tags.set_catalog_number(get_string(file_tags, "CATALOGNUMBER").map(String::from));
+    tags.set_barcode(get_string(file_tags, "BARCODE").map(String::from));
+    tags.set_compilation(get_string(file_tags, "COMPILATION") == Some("1"));
+    tags.set_comment(get_string(file_tags, "COMMENT").map(String::from));
+    tags.set_original_release_date(get_string(file_tags, "ORIGINALDATE").map(String::from));
+    tags.set_number_of_discs(get_usize(file_tags, "DISCTOTAL")?);
+    tags.set_disc_number(get_usize(file_tags, "DISCNUMBER")?);
+    tags.set_disc_title(get_string(file_tags, "DISCSUBTITLE").map(String::from));
+    tags.set_number_of_tracks(get_usize(file_tags, "TRACKTOTAL")?);
+    tags.set_track_number(get_usize(file_tags, "TRACKNUMBER")?);
+    tags.set_track_position(get_string(file_tags, "TRACKPOSITION").map(String::from));
+    tags.set_title(get_string(file_tags, "TITLE").map(String::from));
+    for artist in get_str_vec(file_tags, "ARTIST") {
+        tags.add_artist(artist.to_string());
+    }
+    for (role, item_name) in CREDIT_ITEM_NAMES {
+        for name in get_str_vec(file_tags, item_name) {
+            tags.add_credit(role, name.to_string());
+        }
+    }
+
+    for picture in file_tags.pictures() {
+        if picture.picture_type == PictureType::CoverFront {
+            let format = ImageFormat::from_data(&picture.data)?;
+            let data = picture.data.to_owned();
+            let image = Some(Image { format, data });
+            tags.set_art_work(image);
+            break;
+        }
+    }
+
+    Ok(tags)
+}
+
+/// タグ情報をVorbisコメントと画像に設定する。
+pub fn save_vorbis_comments(file_tag: &mut Tag, tags: &Tags) {
+    set_string(file_tag, "ALBUM", tags.album());
+    set_string(file_tag, "ALBUMARTIST", tags.album_artist());
+    set_string(file_tag, "DATE", tags.release_date());
+    set_string(file_tag, "GENRE", tags.genre());
+    set_string(file_tag, "LABEL", tags.label());
+    set_string(file_tag, "CATALOGNUMBER", tags.catalog_number());
+    set_string(file_tag, "BARCODE", tags.barcode());
+    if tags.compilation() {
+        set_string(file_tag, "COMPILATION", Some("1"));
+    }
+    set_string(file_tag, "COMMENT", tags.comment());
+    set_string(file_tag, "ORIGINALDATE", tags.original_release_date());
+    set_usize(file_tag, "DISCTOTAL", tags.number_of_discs());
+    set_usize(file_tag, "DISCNUMBER", tags.disc_number());
+    set_string(file_tag, "DISCSUBTITLE", tags.disc_title());
+    set_usize(file_tag, "TRACKTOTAL", tags.number_of_tracks());
+    set_usize(file_tag, "TRACKNUMBER", tags.track_number());
+    set_string(file_tag, "TRACKPOSITION", tags.track_position());
+    set_string(file_tag, "TITLE", tags.title());
+    file_tag.set_vorbis("ARTIST", tags.artists.clone());
+    for (role, item_name) in CREDIT_ITEM_NAMES {
+        if !tags.credits(role).is_empty() {
+            file_tag.set_vorbis(item_name, tags.credits(role).clone());
+        }
+    }
+    set_picture(file_tag, PictureType::CoverFront, tags.art_work());
+}
+
+fn get_string<'a>(tags: &'a Tag, item_name: &str) -> Option<&'a str> {
+    match tags.get_vorbis(item_name) {
+        Some(mut value) => Some(value.next().unwrap()),
+        None => None,
+    }
+}
+
+fn set_string(file_tag: &mut Tag, tag_name: &str, s: Option<&str>) {
+    if let Some(s) = s {
+        file_tag.set_vorbis(tag_name, vec![s]);
+    }
+}
+
+/// 数値の項目を読み込む。
+///
+/// "3/12"のように総数が続く値もあるので、先頭の数字だけを読む。
+fn get_usize(tags: &Tag, item_name: &str) -> Result<Option<usize>> {
+    let value = match get_string(tags, item_name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(value, |end| &value[..end]);
+    match usize::from_str(digits) {
+        Ok(number) => Ok(Some(number)),
+        Err(_) => Err(InvalidTagValueError::INSTANCE(format!(
+            "{}を数値に変換できません: {}",
+            item_name, value
+        )))?,
+    }
+}
+
+fn set_usize(file_tag: &mut Tag, tag_name: &str, n: Option<usize>) {
+    if let Some(s) = n {
+        file_tag.set_vorbis(tag_name, vec![s.to_string()]);
+    }
+}
+
+fn get_str_vec<'a>(tags: &'a Tag, item_name: &str) -> Vec<&'a str> {
+    let mut vec = vec![];
+
+    if let Some(values) = tags.get_vorbis(item_name) {
+        for value in values {
+            vec.push(value);
+        }
+    }
+
+    vec
+}
+
+fn set_picture(file_tag: &mut Tag, picture_type: PictureType, image: Option<&Image>) {
+    if let Some(image) = image {
+        file_tag.add_picture(image.format().mime(), picture_type, image.data().clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_tags(item_name: &str, value: &str) -> Tag {
+        let mut file_tags = Tag::new();
+        file_tags.set_vorbis(item_name, vec![value]);
+        file_tags
+    }
+
+    #[test]
+    fn get_usize_reads_leading_number() {
+        assert_eq!(
+            get_usize(&file_tags("TRACKNUMBER", "3/12"), "TRACKNUMBER").unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            get_usize(&file_tags("DISCNUMBER", " 2 "), "DISCNUMBER").unwrap(),
+            Some(2)
+        );
+        assert_eq!(get_usize(&Tag::new(), "TRACKNUMBER").unwrap(), None);
+    }
+
+    #[test]
+    fn get_usize_reports_invalid_value() {
+        let error = get_usize(&file_tags("TRACKNUMBER", "A1"), "TRACKNUMBER").unwrap_err();
+        assert!(error.downcast_ref::<InvalidTagValueError>().is_some());
+        assert_eq!(error.to_string(), "TRACKNUMBERを数値に変換できません: A1");
+    }
+}
@@ -0,0 +1,470 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use id3::{Tag, Version};
+
+use crate::tags::file_common::replace_file;
+use crate::tags::id3_common::{build_id3, load_id3, validate_id3};
+use crate::tags::{InvalidWavError, TagIO, Tags};
+use anyhow::Result;
+
+/// ファイル全体のチャンクのID
+const RIFF_CHUNK_ID: &[u8; 4] = b"RIFF";
+
+/// WAVEのフォームタイプ
+const FORM_TYPE: &[u8; 4] = b"WAVE";
+
+/// リストチャンクのID
+const LIST_CHUNK_ID: &[u8; 4] = b"LIST";
+
+/// 書き込むid3チャンクのID
+const ID3_CHUNK_ID: &[u8; 4] = b"ID3 ";
+
+/// ID3v2タグを持つチャンクのID(大文字と小文字のどちらも使われる)
+const ID3_CHUNK_IDS: [&[u8; 4]; 2] = [b"id3 ", ID3_CHUNK_ID];
+
+/// 情報リストのリストタイプ
+const INFO_LIST_TYPE: &[u8; 4] = b"INFO";
+
+/// アルバム名の情報チャンクのID
+const ALBUM_INFO_ID: &[u8; 4] = b"IPRD";
+
+/// アーティスト名の情報チャンクのID
+const ARTIST_INFO_ID: &[u8; 4] = b"IART";
+
+/// タイトルの情報チャンクのID
+const TITLE_INFO_ID: &[u8; 4] = b"INAM";
+
+/// トラック番号の情報チャンクのID
+const TRACK_NUMBER_INFO_ID: &[u8; 4] = b"ITRK";
+
+/// 発売日の情報チャンクのID
+const RELEASE_DATE_INFO_ID: &[u8; 4] = b"ICRD";
+
+/// 複数のアーティスト名の区切り(ID3のTPE1と同じ)
+const ARTIST_SEPARATOR: char = ';';
+
+/// チャンクヘッダー(IDとサイズ)の長さ
+const CHUNK_HEADER_SIZE: u64 = 8;
+
+/// RIFFチャンクのヘッダーとフォームタイプの長さ(最初のチャンクの位置)
+const FIRST_CHUNK_OFFSET: u64 = CHUNK_HEADER_SIZE + 4;
+
+/// WAVタグIO実装
+///
+/// id3チャンクのID3v2タグと、多くの機器が読むLIST/INFOチャンクの両方にタグ情報を持つ。
+/// 読み込みではID3v2タグを優先し、なければINFOチャンクのアルバム名、アーティスト名、
+/// タイトル、トラック番号、発売日を読み込む。
+pub struct WavIOImpl;
+
+impl TagIO for WavIOImpl {
+    fn load(&self, filepath: &Path) -> Result<Tags> {
+        if let Some(file_tags) = id3::no_tag_ok(Tag::read_from_path(filepath))? {
+            return load_id3(&file_tags);
+        }
+
+        let mut file = File::open(filepath)?;
+        let mut tags = Tags::new();
+        for chunk in read_chunks(&mut file)? {
+            if chunk.is_info_list(&mut file)? {
+                let data = chunk.read_data(&mut file)?;
+                load_info(&mut tags, &data[INFO_LIST_TYPE.len()..]);
+            }
+        }
+
+        Ok(tags)
+    }
+
+    /// 既存のINFOチャンクとid3チャンクを取り除き、作り直したINFOチャンクとid3チャンクをファイル末尾に書き込む。
+    ///
+    /// ID3v2タグを作成できてからファイルを書き換える。
+    /// 途中で失敗しても元のファイルが壊れないように、一時ファイルに書き出して一度に置き換える。
+    fn save(&self, filepath: &Path, tags: &Tags) -> Result<()> {
+        let mut id3_data = vec![];
+        build_id3(tags)?.write_to(&mut id3_data, Version::Id3v24)?;
+        let info_data = build_info(tags);
+
+        let mut file = File::open(filepath)?;
+        let mut kept_chunks = vec![];
+        for chunk in read_chunks(&mut file)? {
+            if !chunk.is_id3() && !chunk.is_info_list(&mut file)? {
+                kept_chunks.push(chunk);
+            }
+        }
+        drop(file);
+
+        let kept_size: u64 = kept_chunks
+            .iter()
+            .map(|chunk| chunk.end() - chunk.offset)
+            .sum();
+        let info_size = info_data.as_deref().map_or(0, chunk_size);
+        let riff_size =
+            FIRST_CHUNK_OFFSET - CHUNK_HEADER_SIZE + kept_size + info_size + chunk_size(&id3_data);
+        if riff_size > u32::MAX as u64 {
+            Err(InvalidWavError::INSTANCE("WAVファイルが大きすぎます"))?
+        }
+
+        replace_file(filepath, |temp_file| {
+            let mut source = File::open(filepath)?;
+            temp_file.write_all(RIFF_CHUNK_ID)?;
+            temp_file.write_all(&(riff_size as u32).to_le_bytes())?;
+            temp_file.write_all(FORM_TYPE)?;
+            for chunk in kept_chunks.iter() {
+                source.seek(SeekFrom::Start(chunk.offset))?;
+                io::copy(
+                    &mut (&mut source).take(chunk.end() - chunk.offset),
+                    temp_file,
+                )?;
+            }
+            if let Some(info_data) = info_data.as_deref() {
+                write_chunk(temp_file, LIST_CHUNK_ID, info_data)?;
+            }
+            write_chunk(temp_file, ID3_CHUNK_ID, &id3_data)
+        })
+    }
+
+    fn validate(&self, tags: &Tags) -> Result<()> {
+        validate_id3(tags)
+    }
+}
+
+/// RIFFチャンク直下のチャンク
+struct Chunk {
+    id: [u8; 4],
+    /// チャンクヘッダーの位置
+    offset: u64,
+    /// ヘッダーとパディングを含まないデータの長さ
+    size: u64,
+}
+
+impl Chunk {
+    /// パディングを含むチャンクの終わりの位置を返す。
+    fn end(&self) -> u64 {
+        self.offset + CHUNK_HEADER_SIZE + self.size + self.size % 2
+    }
+
+    fn read_data(&self, file: &mut File) -> Result<Vec<u8>> {
+        let mut data = vec![0; self.size as usize];
+        file.seek(SeekFrom::Start(self.offset + CHUNK_HEADER_SIZE))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// ID3v2タグのチャンクかを判定する。
+    fn is_id3(&self) -> bool {
+        ID3_CHUNK_IDS.contains(&&self.id)
+    }
+
+    /// 情報リストのLISTチャンクかを判定する。
+    fn is_info_list(&self, file: &mut File) -> Result<bool> {
+        if &self.id != LIST_CHUNK_ID || self.size < INFO_LIST_TYPE.len() as u64 {
+            return Ok(false);
+        }
+
+        let mut list_type = [0; 4];
+        file.seek(SeekFrom::Start(self.offset + CHUNK_HEADER_SIZE))?;
+        file.read_exact(&mut list_type)?;
+        Ok(&list_type == INFO_LIST_TYPE)
+    }
+}
+
+/// RIFFチャンク直下のチャンクを一覧にする。
+fn read_chunks(file: &mut File) -> Result<Vec<Chunk>> {
+    let mut header = [0; FIRST_CHUNK_OFFSET as usize];
+    if file.read_exact(&mut header).is_err()
+        || &header[..4] != RIFF_CHUNK_ID
+        || &header[8..] != FORM_TYPE
+    {
+        Err(InvalidWavError::INSTANCE("WAVファイルではありません"))?
+    }
+    let riff_end = CHUNK_HEADER_SIZE + u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+    let riff_end = riff_end.min(file.metadata()?.len());
+
+    let mut chunks = vec![];
+    let mut offset = FIRST_CHUNK_OFFSET;
+    while offset + CHUNK_HEADER_SIZE <= riff_end {
+        let mut chunk_header = [0; CHUNK_HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut chunk_header)?;
+        let chunk = Chunk {
+            id: chunk_header[..4].try_into().unwrap(),
+            offset,
+            size: u32::from_le_bytes(chunk_header[4..].try_into().unwrap()) as u64,
+        };
+        if chunk.offset + CHUNK_HEADER_SIZE + chunk.size > riff_end {
+            Err(InvalidWavError::INSTANCE("チャンクが途中で切れています"))?
+        }
+        offset = chunk.end();
+        chunks.push(chunk);
+    }
+
+    Ok(chunks)
+}
+
+/// INFOチャンクの中の情報チャンクをタグ情報にする。
+fn load_info(tags: &mut Tags, mut data: &[u8]) {
+    while data.len() >= CHUNK_HEADER_SIZE as usize {
+        let id: [u8; 4] = data[..4].try_into().unwrap();
+        let size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        data = &data[CHUNK_HEADER_SIZE as usize..];
+        let value = &data[..size.min(data.len())];
+        data = &data[(size + size % 2).min(data.len())..];
+
+        // 文字列はNUL終端で、文字コードの規定はないのでUTF-8として読む
+        let value = String::from_utf8_lossy(value)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        if value.is_empty() {
+            continue;
+        }
+        match &id {
+            ALBUM_INFO_ID => tags.set_album(Some(value)),
+            ARTIST_INFO_ID => {
+                for artist in value.split(ARTIST_SEPARATOR) {
+                    tags.add_artist(artist.trim().to_string());
+                }
+            }
+            TITLE_INFO_ID => tags.set_title(Some(value)),
+            TRACK_NUMBER_INFO_ID => tags.set_track_number(value.parse().ok()),
+            RELEASE_DATE_INFO_ID => tags.set_release_date(Some(value)),
+            _ => (),
+        }
+    }
+}
+
+/// タグ情報からINFOチャンクのデータを作成する。
+///
+/// 設定する値がなければNoneを返す。
+fn build_info(tags: &Tags) -> Option<Vec<u8>> {
+    let artists = tags.artists().join(&ARTIST_SEPARATOR.to_string());
+    let track_number = tags.track_number().map(|n| n.to_string());
+    let items = [
+        (ALBUM_INFO_ID, tags.album()),
+        (ARTIST_INFO_ID, Some(artists.as_str())),
+        (TITLE_INFO_ID, tags.title()),
+        (TRACK_NUMBER_INFO_ID, track_number.as_deref()),
+        (RELEASE_DATE_INFO_ID, tags.release_date()),
+    ];
+
+    let mut data = INFO_LIST_TYPE.to_vec();
+    for (id, value) in items {
+        let value = match value {
+            Some(value) if !value.is_empty() => value,
+            _ => continue,
+        };
+        let size = value.len() + 1;
+        data.extend(id);
+        data.extend((size as u32).to_le_bytes());
+        data.extend(value.as_bytes());
+        data.push(0);
+        if size % 2 == 1 {
+            data.push(0);
+        }
+    }
+
+    if data.len() == INFO_LIST_TYPE.len() {
+        None
+    } else {
+        Some(data)
+    }
+}
+
+/// チャンクをヘッダーとパディングを付けて書き込む。
+fn write_chunk(file: &mut File, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    file.write_all(id)?;
+    file.write_all(&(data.len() as u32).to_le_bytes())?;
+    file.write_all(data)?;
+    if data.len() % 2 == 1 {
+        file.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+/// パディングを含むチャンク全体の長さを返す。
+fn chunk_size(data: &[u8]) -> u64 {
+    CHUNK_HEADER_SIZE + data.len() as u64 + data.len() as u64 % 2
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn info_chunk(title: &str) -> Vec<u8> {
+        let mut tags = Tags::new();
+        tags.set_title(Some(title.to_string()));
+        chunk(LIST_CHUNK_ID, &build_info(&tags).unwrap())
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = [FORM_TYPE.to_vec(), chunks.concat()].concat();
+        [
+            RIFF_CHUNK_ID.to_vec(),
+            (body.len() as u32).to_le_bytes().to_vec(),
+            body,
+        ]
+        .concat()
+    }
+
+    fn tags_with_title(title: &str) -> Tags {
+        let mut tags = Tags::new();
+        tags.set_title(Some(title.to_string()));
+        tags.add_artist("Artist".to_string());
+        tags
+    }
+
+    fn chunk_ids(filepath: &Path) -> Vec<[u8; 4]> {
+        read_chunks(&mut File::open(filepath).unwrap())
+            .unwrap()
+            .iter()
+            .map(|chunk| chunk.id)
+            .collect()
+    }
+
+    fn riff_size(filepath: &Path) -> u64 {
+        let contents = fs::read(filepath).unwrap();
+        u32::from_le_bytes(contents[4..8].try_into().unwrap()) as u64
+    }
+
+    /// INFOチャンクだけを読み込む。
+    fn load_info_only(filepath: &Path) -> Tags {
+        let mut file = File::open(filepath).unwrap();
+        let mut tags = Tags::new();
+        for chunk in read_chunks(&mut file).unwrap() {
+            if chunk.is_info_list(&mut file).unwrap() {
+                let data = chunk.read_data(&mut file).unwrap();
+                load_info(&mut tags, &data[INFO_LIST_TYPE.len()..]);
+            }
+        }
+        tags
+    }
+
+    #[test]
+    fn save_moves_non_trailing_info_chunk_to_end() {
+        let sound_data = [0x69; 11];
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            wav(&[
+                chunk(b"fmt ", &[0; 16]),
+                info_chunk("Old"),
+                chunk(b"data", &sound_data),
+            ]),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(file.path(), fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        WavIOImpl
+            .save(file.path(), &tags_with_title("New"))
+            .unwrap();
+
+        assert_eq!(
+            chunk_ids(file.path()),
+            vec![*b"fmt ", *b"data", *LIST_CHUNK_ID, *b"ID3 "]
+        );
+        let contents = fs::read(file.path()).unwrap();
+        assert_eq!(riff_size(file.path()), contents.len() as u64 - 8);
+        // 音声データとパディングは移動しても変わらない
+        let chunks = read_chunks(&mut File::open(file.path()).unwrap()).unwrap();
+        assert_eq!(
+            chunks[1]
+                .read_data(&mut File::open(file.path()).unwrap())
+                .unwrap(),
+            sound_data
+        );
+        assert_eq!(contents[chunks[1].end() as usize - 1], 0);
+        let info_tags = load_info_only(file.path());
+        assert_eq!(info_tags.title(), Some("New"));
+        assert_eq!(info_tags.artists(), &vec!["Artist".to_string()]);
+        assert_eq!(WavIOImpl.load(file.path()).unwrap().title(), Some("New"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(file.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+    }
+
+    #[test]
+    fn save_replaces_info_and_id3_chunks() {
+        let mut id3_data = vec![];
+        build_id3(&tags_with_title("Old"))
+            .unwrap()
+            .write_to(&mut id3_data, Version::Id3v24)
+            .unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            wav(&[
+                chunk(b"fmt ", &[0; 16]),
+                chunk(b"data", &[0x69; 8]),
+                info_chunk("Old"),
+                chunk(b"id3 ", &id3_data),
+            ]),
+        )
+        .unwrap();
+
+        WavIOImpl
+            .save(file.path(), &tags_with_title("A fairly long new title"))
+            .unwrap();
+        assert_eq!(
+            chunk_ids(file.path()),
+            vec![*b"fmt ", *b"data", *LIST_CHUNK_ID, *ID3_CHUNK_ID]
+        );
+        let first_len = fs::metadata(file.path()).unwrap().len();
+        assert_eq!(riff_size(file.path()), first_len - 8);
+        assert_eq!(
+            load_info_only(file.path()).title(),
+            Some("A fairly long new title")
+        );
+        assert_eq!(
+            WavIOImpl.load(file.path()).unwrap().title(),
+            Some("A fairly long new title")
+        );
+
+        // 書き込む値がなければINFOチャンクを取り除き、短くなった分だけファイルも短くなる
+        WavIOImpl.save(file.path(), &Tags::new()).unwrap();
+        assert_eq!(
+            chunk_ids(file.path()),
+            vec![*b"fmt ", *b"data", *ID3_CHUNK_ID]
+        );
+        let second_len = fs::metadata(file.path()).unwrap().len();
+        assert!(second_len < first_len);
+        assert_eq!(riff_size(file.path()), second_len - 8);
+        assert_eq!(WavIOImpl.load(file.path()).unwrap().title(), None);
+    }
+
+    #[test]
+    fn save_keeps_file_when_id3_cannot_be_built() {
+        let contents = wav(&[
+            chunk(b"fmt ", &[0; 16]),
+            info_chunk("Old"),
+            chunk(b"data", &[0x69; 8]),
+        ]);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), &contents).unwrap();
+
+        let mut tags = tags_with_title("New");
+        tags.set_release_date(Some("not a date".to_string()));
+        assert!(WavIOImpl.save(file.path(), &tags).is_err());
+
+        assert_eq!(fs::read(file.path()).unwrap(), contents);
+    }
+}
@@ -1,70 +1,266 @@
+use std::fmt::{self, Display, Formatter};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use once_cell::sync::Lazy;
 use regex;
 use regex::Regex;
+use serde::Serialize;
 use unicode_normalization::UnicodeNormalization;
 
-use crate::model::{AlbumInfo, DiscInfo};
-use crate::tags::{Image, ImageFormat};
+use crate::model::{AlbumInfo, DiscInfo, Role};
+use crate::tags::{Field, Image, ImageFormat};
+use crate::tags_file::structured_format::StructuredFormat;
 use anyhow::Result;
 use thiserror::Error;
 
+mod cue_sheet;
+mod structured_format;
+
+/// コメント行の先頭の文字
+const COMMENT_PREFIX: char = '#';
+
+/// エスケープに使う文字
+const ESCAPE: char = '\\';
+
+/// 音楽ファイルの現在の値を残す印
+const KEEP_MARKER: char = '*';
+
+/// 対応付ける音楽ファイル名の前に置く文字
+const ANCHOR_PREFIX: char = '@';
+
+/// トラック情報の区切り
+const SEPARATOR: &str = "//";
+
+/// ディスク情報の行
+static DISC_HEADER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^==\s*(?P<header>.*?)\s*==$").unwrap());
+
+/// ディスクタイトルの前のディスク番号
+static DISC_NUMBER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^disc\s*(?P<number>\d+)\s*:\s*(?P<title>.*)$").unwrap());
+
+/// 行末の「 @ ファイル名」
+static ANCHOR_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?P<track>.*\S)\s+@\s+(?P<filename>\S.*)$").unwrap());
+
+/// 発売日
+static RELEASE_DATE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap());
+
+/// オリジナル発売日(年だけ、年月だけでもよい)
+static ORIGINAL_RELEASE_DATE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\d{4}(-\d{2}(-\d{2})?)?$").unwrap());
+
+/// タイトルの前のトラック番号または位置
+static TRACK_NUMBER_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?P<position>(?P<side>[A-Za-z])?(?P<number>\d+))\.\s+").unwrap());
+
 /// tagsファイルを読み込んでアルバム情報を作成する。
-pub fn load_tags_file(tags_filepath: &Path) -> Result<AlbumInfo> {
-    let tags_file_contents = read_tags_file(tags_filepath)?;
+///
+/// 拡張子がjson、toml、yaml(yml)なら構造化形式、cueならCUEシート、それ以外はテキスト形式として読み込む。
+/// アートワークは指定されたフォルダの画像ファイルから読み込む。
+pub fn load_tags_file(tags_filepath: &Path, art_work_folder: &Path) -> Result<AlbumInfo> {
+    let mut album_info = match StructuredFormat::from_filepath(tags_filepath) {
+        Some(format) => structured_format::load(tags_filepath, format)?,
+        None if cue_sheet::is_cue_sheet(tags_filepath) => cue_sheet::load(tags_filepath)?,
+        None => parse_tags_file(&read_tags_file(tags_filepath)?)?,
+    };
+
+    if let Some(image_filepath) = find_image_file(art_work_folder)? {
+        let image_format = ImageFormat::from_filepath(image_filepath.as_path())?;
+        let image_data = fs::read(image_filepath)?;
+        let image = Image::new(image_format, image_data);
+        album_info.set_art_work(Some(image));
+    }
+
+    Ok(album_info)
+}
+
+/// tagsファイルの内容をパースしてアルバム情報を作成する。
+///
+/// 誤りがあっても続きを読めるかぎり最後まで読み、見つかった誤りをまとめてエラーにする。
+fn parse_tags_file(tags_file_contents: &str) -> Result<AlbumInfo> {
+    let mut diagnostics = vec![];
 
-    let mut lines = tags_file_contents.lines();
+    // 行番号は1から数え、コメント行はどこにあっても読み飛ばす
+    let mut lines = tags_file_contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| !line.trim_start().starts_with(COMMENT_PREFIX));
+    let end_line_number = tags_file_contents.lines().count() + 1;
 
-    let album = read_line(lines.next(), "アルバム名がありません。")?;
-    let album_artist = read_line(lines.next(), "アルバムアーティスト名がありません。")?;
-    let release_date = read_line(lines.next(), "発売日がありません。")?;
-    if !Regex::new(r"^\d{4}-\d{2}-\d{2}$")
-        .unwrap()
-        .is_match(release_date)
-    {
-        Err(LoadTagsError::INSTANCE("発売日の形式が不正です。"))?
+    let mut kept_fields = vec![];
+    let (_, line) = read_line(
+        &mut lines,
+        end_line_number,
+        "アルバム名がありません。",
+        &mut diagnostics,
+    )?;
+    let album = parse_value(line.trim(), Field::Album, &mut kept_fields);
+    let (_, line) = read_line(
+        &mut lines,
+        end_line_number,
+        "アルバムアーティスト名がありません。",
+        &mut diagnostics,
+    )?;
+    let album_artist = parse_value(line.trim(), Field::AlbumArtist, &mut kept_fields);
+    let (line_number, line) = read_line(
+        &mut lines,
+        end_line_number,
+        "発売日がありません。",
+        &mut diagnostics,
+    )?;
+    let release_date = parse_value(line.trim(), Field::ReleaseDate, &mut kept_fields);
+    if let Some(release_date) = &release_date {
+        if !RELEASE_DATE_REGEX.is_match(release_date) {
+            diagnostics.push(Diagnostic::new(
+                line_number,
+                line,
+                line.trim(),
+                "発売日の形式が不正です。",
+            ));
+        }
     }
 
-    let mut album_info = AlbumInfo::new(
-        Some(album.to_string()),
-        Some(album_artist.to_string()),
-        Some(release_date.to_string()),
-    );
+    let mut album_info = AlbumInfo::new(album, album_artist, release_date);
+    for field in kept_fields {
+        album_info.keep_field(field);
+    }
 
-    if read_line(lines.next(), "発売日の次の空白行がありませんでした。")?.len() > 0
-    {
-        Err(LoadTagsError::INSTANCE(
-            "発売日の次の行が空白行ではありません。",
-        ))?
+    // 発売日の次から空白行までは「項目名: 値」形式の追加のアルバム情報
+    let mut header_keys = vec![];
+    loop {
+        let (line_number, line) = read_line(
+            &mut lines,
+            end_line_number,
+            "発売日の次の空白行がありませんでした。",
+            &mut diagnostics,
+        )?;
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        match parse_header(header, &mut album_info) {
+            Ok(key) if header_keys.contains(&key) => diagnostics.push(Diagnostic::new(
+                line_number,
+                line,
+                header,
+                "アルバム情報の項目が重複しています。",
+            )),
+            Ok(key) => header_keys.push(key),
+            Err(problem) => diagnostics.push(problem.at(line_number, line)),
+        }
     }
 
     let mut current_disc_info: &mut DiscInfo = album_info.new_disc();
+    let mut disc_number = 1;
+    let mut disc_header = None;
 
-    loop {
-        let line = match lines.next() {
-            Some(line) => line.trim_end(),
-            None => break,
-        };
+    // トラック番号と位置はディスクごと、ファイル名はアルバム全体で重複してはいけない
+    let mut track_number = 0;
+    let mut numbers = vec![];
+    let mut positions = vec![];
+    let mut filenames = vec![];
+
+    for (line_number, line) in lines {
+        let line = line.trim_end();
 
-        if line.len() == 0 {
-            if current_disc_info.tracks().len() == 0 {
-                Err(LoadTagsError::INSTANCE("空白行が連続しています。"))?
+        if line.is_empty() {
+            if current_disc_info.tracks().is_empty() {
+                match disc_header {
+                    Some((header_line_number, header_line)) => {
+                        diagnostics.push(Diagnostic::new(
+                            header_line_number,
+                            header_line,
+                            header_line,
+                            "ディスクにトラックがありません。",
+                        ));
+                    }
+                    None => {
+                        diagnostics.push(Diagnostic::new(
+                            line_number,
+                            line,
+                            line,
+                            "空白行が連続しています。",
+                        ));
+                        continue;
+                    }
+                }
             }
             current_disc_info = album_info.new_disc();
+            disc_number += 1;
+            disc_header = None;
+            track_number = 0;
+            numbers.clear();
+            positions.clear();
+        } else if current_disc_info.tracks().is_empty()
+            && disc_header.is_none()
+            && DISC_HEADER_REGEX.is_match(line)
+        {
+            // ディスクの最初の行だけはディスク情報として扱う
+            if let Err(problem) = parse_disc_header(line, disc_number, current_disc_info) {
+                diagnostics.push(problem.at(line_number, line));
+            }
+            disc_header = Some((line_number, line));
         } else {
-            let (title, artists) = parse_track(line)?;
-            current_disc_info.new_track(Some(title), artists);
+            if let Err(problem) = parse_track(line, current_disc_info) {
+                diagnostics.push(problem.at(line_number, line));
+                continue;
+            }
+
+            let track_info = current_disc_info.tracks().last().unwrap();
+            track_number = track_info.number().unwrap_or(track_number + 1);
+            if numbers.contains(&track_number) {
+                diagnostics.push(Diagnostic::new(
+                    line_number,
+                    line,
+                    line,
+                    "トラック番号が重複しています。",
+                ));
+            }
+            numbers.push(track_number);
+
+            if let Some(position) = track_info.position() {
+                if positions.iter().any(|other| other == position) {
+                    diagnostics.push(Diagnostic::new(
+                        line_number,
+                        line,
+                        line,
+                        "トラックの位置が重複しています。",
+                    ));
+                }
+                positions.push(position.to_string());
+            }
+
+            if let Some(filename) = track_info.filename() {
+                if filenames.iter().any(|other| other == filename) {
+                    let captures = ANCHOR_REGEX.captures(line).unwrap();
+                    diagnostics.push(Diagnostic::new(
+                        line_number,
+                        line,
+                        captures.name("filename").unwrap().as_str(),
+                        "同じファイル名が複数のトラックに指定されています。",
+                    ));
+                }
+                filenames.push(filename.to_string());
+            }
         }
     }
 
-    let tags_full_filepath = tags_filepath.canonicalize()?;
-    let folder = tags_full_filepath.parent().unwrap();
-    if let Some(image_filepath) = find_image_file(folder)? {
-        let image_format = ImageFormat::from_filepath(image_filepath.as_path())?;
-        let image_data = fs::read(image_filepath)?;
-        let image = Image::new(image_format, image_data);
-        album_info.set_art_work(Some(image));
+    if let Some((header_line_number, header_line)) = disc_header {
+        if current_disc_info.tracks().is_empty() {
+            diagnostics.push(Diagnostic::new(
+                header_line_number,
+                header_line,
+                header_line,
+                "ディスクにトラックがありません。",
+            ));
+        }
+    }
+
+    if !diagnostics.is_empty() {
+        Err(ParseTagsError::INSTANCE(diagnostics))?
     }
 
     Ok(album_info)
@@ -86,28 +282,343 @@ fn read_tags_file(tags_filepath: &Path) -> Result<String> {
     Ok(tags_file_contents.nfc().collect::<String>())
 }
 
-fn read_line<'a>(line: Option<&'a str>, missing_error_message: &'static str) -> Result<&'a str> {
-    match line {
-        Some(line) => Ok(line.trim()),
-        None => Err(LoadTagsError::INSTANCE(missing_error_message))?,
+/// 次の行を行番号とともに返す。
+///
+/// 行がなければ、それまでに見つかった誤りに加えてエラーにする。
+fn read_line<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    end_line_number: usize,
+    missing_error_message: &'static str,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<(usize, &'a str)> {
+    match lines.next() {
+        Some(line) => Ok(line),
+        None => {
+            diagnostics.push(Diagnostic::new(
+                end_line_number,
+                "",
+                "",
+                missing_error_message,
+            ));
+            Err(ParseTagsError::INSTANCE(std::mem::take(diagnostics)))?
+        }
     }
 }
 
-/// トラックの行をパースする。
-fn parse_track(line: &str) -> Result<(String, Vec<String>)> {
-    let mut split_line = line.split("//");
+/// アルバム情報の「項目名: 値」の行をパースしてアルバム情報に設定する。
+///
+/// 設定した項目名を返す。
+fn parse_header<'a>(line: &'a str, album_info: &mut AlbumInfo) -> ParseResult<'a, &'a str> {
+    let (key, value) = match line.split_once(':') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => Err(Problem::new(
+            line,
+            "発売日の次の行が空白行でも「項目名: 値」の形式でもありません。",
+        ))?,
+    };
+    if value.is_empty() {
+        Err(Problem::new(
+            &line[line.len()..],
+            "アルバム情報の値がありません。",
+        ))?
+    }
+
+    let field = match header_field(key) {
+        Some(field) => field,
+        None => Err(Problem::new(key, "アルバム情報の項目名が不正です。"))?,
+    };
+    if is_keep_marker(value) {
+        album_info.keep_field(field);
+        return Ok(key);
+    }
+
+    let raw_value = value;
+    let value = Some(unescape(value));
+    match key {
+        "genre" => album_info.set_genre(value),
+        "label" => album_info.set_label(value),
+        "catalog_number" => album_info.set_catalog_number(value),
+        "barcode" => album_info.set_barcode(value),
+        "compilation" => match value.as_deref() {
+            Some("true") => album_info.set_compilation(true),
+            Some("false") => album_info.set_compilation(false),
+            _ => Err(Problem::new(
+                raw_value,
+                "compilationにはtrueかfalseを指定してください。",
+            ))?,
+        },
+        "comment" => album_info.set_comment(value),
+        "original_release_date" => {
+            if !ORIGINAL_RELEASE_DATE_REGEX.is_match(value.as_deref().unwrap()) {
+                Err(Problem::new(
+                    raw_value,
+                    "オリジナル発売日の形式が不正です。",
+                ))?
+            }
+            album_info.set_original_release_date(value)
+        }
+        _ => Err(Problem::new(key, "アルバム情報の項目名が不正です。"))?,
+    }
+
+    Ok(key)
+}
+
+/// アルバム情報の項目名に対応するタグ情報の項目を返す。
+fn header_field(key: &str) -> Option<Field> {
+    match key {
+        "genre" => Some(Field::Genre),
+        "label" => Some(Field::Label),
+        "catalog_number" => Some(Field::CatalogNumber),
+        "barcode" => Some(Field::Barcode),
+        "compilation" => Some(Field::Compilation),
+        "comment" => Some(Field::Comment),
+        "original_release_date" => Some(Field::OriginalReleaseDate),
+        _ => None,
+    }
+}
+
+/// エスケープを解除した値を返す。
+/// 現在の値を残す印なら項目を記録してNoneを返す。
+fn parse_value(value: &str, field: Field, kept_fields: &mut Vec<Field>) -> Option<String> {
+    if is_keep_marker(value) {
+        kept_fields.push(field);
+        None
+    } else {
+        Some(unescape(value))
+    }
+}
+
+/// エスケープされていない「*」だけの値かを判定する。
+fn is_keep_marker(value: &str) -> bool {
+    let mut chars = value.trim().chars();
+    chars.next() == Some(KEEP_MARKER) && chars.next().is_none()
+}
+
+/// アーティスト名の並びをパースする。
+///
+/// 現在の値を残す印は1つだけで書かれている場合に限り、Noneを返す。
+fn parse_artists<'a>(fields: &[&'a str]) -> ParseResult<'a, Option<Vec<String>>> {
+    let keep_marker = match fields.iter().find(|field| is_keep_marker(field)) {
+        Some(keep_marker) => keep_marker,
+        None => return Ok(Some(fields.iter().map(|field| unescape(field)).collect())),
+    };
+    if fields.len() > 1 {
+        Err(Problem::new(
+            keep_marker,
+            "「*」は他のアーティスト名と一緒に書けません。",
+        ))?
+    }
+
+    Ok(None)
+}
+
+/// 「== ディスクタイトル//アーティスト名 ==」の形式のディスク情報の行をパースする。
+///
+/// ディスクタイトルの前に「Disc 2:」のようにディスク番号を書いてもよいが、
+/// 何枚目のディスクかと一致していること。
+fn parse_disc_header<'a>(
+    line: &'a str,
+    disc_number: usize,
+    disc_info: &mut DiscInfo,
+) -> ParseResult<'a, ()> {
+    let captures = DISC_HEADER_REGEX.captures(line).unwrap();
+
+    let fields: Vec<&str> = split_fields(captures.name("header").unwrap().as_str())
+        .into_iter()
+        .map(str::trim)
+        .collect();
+    let title = fields[0];
+
+    let title = match DISC_NUMBER_REGEX.captures(title) {
+        Some(captures) => {
+            let number = captures.name("number").unwrap().as_str();
+            if number.parse::<usize>().ok() != Some(disc_number) {
+                Err(Problem::new(
+                    number,
+                    "ディスク情報のディスク番号が何枚目のディスクかと一致しません。",
+                ))?
+            }
+            captures.name("title").unwrap().as_str()
+        }
+        None => title,
+    };
+
+    if is_keep_marker(title) {
+        disc_info.keep_field(Field::DiscTitle);
+    } else if !title.is_empty() {
+        disc_info.set_title(Some(unescape(title)));
+    }
+    match parse_artists(&fields[1..])? {
+        Some(artists) => disc_info.set_artists(artists),
+        None => disc_info.keep_field(Field::Artists),
+    }
+
+    Ok(())
+}
+
+/// トラックの行をパースしてディスク情報にトラック情報を追加する。
+///
+/// タイトルの後に//で区切ってアーティスト名を並べる。
+/// 「作詞:名前」のように役割名を付けたものはクレジットとして扱う。
+/// 行末に「 @ ファイル名」があれば、そのファイルに対応付ける。
+fn parse_track<'a>(line: &'a str, disc_info: &mut DiscInfo) -> ParseResult<'a, ()> {
+    let (line, filename) = match ANCHOR_REGEX.captures(line) {
+        Some(captures) => (
+            captures.name("track").unwrap().as_str(),
+            Some(captures["filename"].to_string()),
+        ),
+        None => (line, None),
+    };
+
+    let mut split_line = split_fields(line).into_iter();
 
     let title = match split_line.next() {
         Some(title) => title,
-        None => Err(LoadTagsError::INSTANCE("タイトルがありません。"))?,
+        None => Err(Problem::new(line, "タイトルがありません。"))?,
     };
 
     let mut artists = vec![];
+    let mut credits = vec![];
     for artist in split_line {
-        artists.push(artist.to_string());
+        match parse_credit(&unescape(artist)) {
+            Some(credit) => credits.push(credit),
+            None => artists.push(artist),
+        }
+    }
+    let artists = parse_artists(&artists)?;
+    let keeps_artists = artists.is_none();
+
+    let (number, position, title) = parse_track_number(title);
+    let keeps_title = is_keep_marker(title);
+    let title = if keeps_title {
+        None
+    } else {
+        Some(unescape(title))
+    };
+
+    let track_info = disc_info.new_track(title, artists.unwrap_or_default());
+    if keeps_title {
+        track_info.keep_field(Field::Title);
+    }
+    if keeps_artists {
+        track_info.keep_field(Field::Artists);
+    }
+    track_info.set_number(number);
+    track_info.set_position(position);
+    track_info.set_filename(filename);
+    for (role, name) in credits {
+        track_info.add_credit(role, name);
+    }
+
+    Ok(())
+}
+
+/// タイトルの前に「07. 」や「B2. 」の形式で書かれたトラック番号や位置をパースする。
+///
+/// 位置の場合はトラック番号を明示せず、前のトラックの次の番号にする。
+fn parse_track_number(title: &str) -> (Option<usize>, Option<String>, &str) {
+    let captures = match TRACK_NUMBER_REGEX.captures(title) {
+        Some(captures) => captures,
+        None => return (None, None, title),
+    };
+    let rest = &title[captures.get(0).unwrap().end()..];
+
+    match captures.name("side") {
+        Some(_) => (None, Some(captures["position"].to_string()), rest),
+        None => (captures["number"].parse().ok(), None, rest),
+    }
+}
+
+/// 行を//で区切る。
+///
+/// エスケープされた/は区切りとみなさない。
+/// 現在の値を残す印と区別できるように、エスケープは解除せずに返す。
+fn split_fields(line: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut start = 0;
+
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (c, chars.peek()) {
+            (ESCAPE, Some(&(_, next))) if is_escapable(next) => {
+                chars.next();
+            }
+            ('/', Some((_, '/'))) => {
+                fields.push(&line[start..i]);
+                start = i + 2;
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    fields.push(&line[start..]);
+
+    fields
+}
+
+/// 区切りのない値のエスケープを解除する。
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            (ESCAPE, Some(&next)) if is_escapable(next) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// エスケープ文字の後に置くとその文字自体を表す文字かを判定する。
+fn is_escapable(c: char) -> bool {
+    c == '/' || c == ESCAPE || c == COMMENT_PREFIX || c == KEEP_MARKER || c == ANCHOR_PREFIX
+}
+
+/// tagsファイルに書く値をエスケープする。
+///
+/// 区切りとみなされる/、エスケープとみなされるエスケープ文字、
+/// 行頭でコメントとみなされる#、現在の値を残す印とみなされる*、
+/// ファイル名の指定とみなされる空白の後の@の前にエスケープ文字を置く。
+/// 値の末尾の/とエスケープ文字は、後に区切りが続いても区別できるようにエスケープする。
+fn escape(s: &str, line_start: bool) -> String {
+    let mut escaped = String::new();
+
+    let chars: Vec<char> = s.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        let next = chars.get(i + 1).copied();
+        let needs_escape = match c {
+            '/' => next.is_none() || next == Some('/'),
+            ESCAPE => next.is_none() || next.is_some_and(is_escapable),
+            COMMENT_PREFIX => line_start && chars[..i].iter().all(|c| c.is_whitespace()),
+            KEEP_MARKER => is_keep_marker(s),
+            ANCHOR_PREFIX => i > 0 && chars[i - 1].is_whitespace(),
+            _ => false,
+        };
+        if needs_escape {
+            escaped.push(ESCAPE);
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// 「役割名:名前」の形式ならクレジットとしてパースする。
+/// 区切りのコロンは全角でもよい。
+fn parse_credit(s: &str) -> Option<(Role, String)> {
+    let (role, name) = s.split_once([':', '：'])?;
+    let role = Role::from_name(role.trim())?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
     }
 
-    Ok((title.to_string(), artists))
+    Some((role, name.to_string()))
 }
 
 /// 画像ファイルを探す。
@@ -122,57 +633,199 @@ fn find_image_file(folder: &Path) -> Result<Option<PathBuf>> {
 }
 
 /// tagsファイルを出力する。
+///
+/// 値に区切りやコメントとみなされる文字が含まれていればエスケープする。
+/// 構造化形式の拡張子ならその形式で出力する。
 pub fn write_tags_file(tags_filepath: &Path, album_info: &AlbumInfo) -> Result<()> {
+    if let Some(format) = StructuredFormat::from_filepath(tags_filepath) {
+        return structured_format::write(tags_filepath, format, album_info);
+    }
+    if cue_sheet::is_cue_sheet(tags_filepath) {
+        return cue_sheet::write();
+    }
+
+    if let Err(_) = fs::write(tags_filepath, format_tags_file(album_info)) {
+        Err(WriteTagsError::INSTANCE(
+            "tagsファイルが書き込めませんでした。",
+        ))?
+    };
+
+    Ok(())
+}
+
+/// アルバム情報をテキスト形式のtagsファイルの内容にする。
+fn format_tags_file(album_info: &AlbumInfo) -> String {
     let mut s = String::new();
 
-    s.push_str(album_info.album().unwrap_or(""));
+    s.push_str(&escape(album_info.album().unwrap_or(""), true));
     s.push('\n');
 
-    s.push_str(album_info.album_artist().unwrap_or(""));
+    s.push_str(&escape(album_info.album_artist().unwrap_or(""), true));
     s.push('\n');
 
-    s.push_str(album_info.release_date().unwrap_or(""));
+    s.push_str(&escape(album_info.release_date().unwrap_or(""), true));
     s.push('\n');
 
+    push_header(&mut s, "genre", album_info.genre());
+    push_header(&mut s, "label", album_info.label());
+    push_header(&mut s, "catalog_number", album_info.catalog_number());
+    push_header(&mut s, "barcode", album_info.barcode());
+    if album_info.compilation() {
+        push_header(&mut s, "compilation", Some("true"));
+    }
+    push_header(&mut s, "comment", album_info.comment());
+    push_header(
+        &mut s,
+        "original_release_date",
+        album_info.original_release_date(),
+    );
+
     for disc_info in album_info.discs() {
         s.push('\n');
 
+        if disc_info.title().is_some() || !disc_info.artists().is_empty() {
+            s.push_str("== ");
+            s.push_str(&escape(disc_info.title().unwrap_or(""), false));
+            for artist in disc_info.artists() {
+                s.push_str(SEPARATOR);
+                s.push_str(&escape(artist, false));
+            }
+            s.push_str(" ==\n");
+        }
+
+        let mut track_number = 0;
         for track_info in disc_info.tracks() {
-            s.push_str(track_info.title().unwrap_or(""));
+            let title = track_info.title().unwrap_or("");
+            // トラック番号やディスク情報のように見えるタイトルは番号を明示して区別できるようにする
+            let number = match (track_info.position(), track_info.number()) {
+                (Some(position), _) => Some(position.to_string()),
+                (None, Some(number)) => Some(format!("{:02}", number)),
+                (None, None)
+                    if TRACK_NUMBER_REGEX.is_match(title) || DISC_HEADER_REGEX.is_match(title) =>
+                {
+                    Some(format!("{:02}", track_number + 1))
+                }
+                (None, None) => None,
+            };
+            track_number = track_info.number().unwrap_or(track_number + 1);
+            if let Some(number) = &number {
+                s.push_str(number);
+                s.push_str(". ");
+            }
+            s.push_str(&escape(title, number.is_none()));
 
             for artist in track_info.artists() {
-                s.push_str("//");
-                s.push_str(artist);
+                s.push_str(SEPARATOR);
+                s.push_str(&escape(artist, false));
+            }
+
+            for role in Role::ALL {
+                for name in track_info.credits(role) {
+                    s.push_str(SEPARATOR);
+                    s.push_str(role.name());
+                    s.push(':');
+                    s.push_str(&escape(name, false));
+                }
+            }
+
+            if let Some(filename) = track_info.filename() {
+                s.push_str("  ");
+                s.push(ANCHOR_PREFIX);
+                s.push(' ');
+                s.push_str(filename);
             }
 
             s.push('\n');
         }
     }
 
-    let nfc = s.nfc().collect::<String>();
+    s.nfc().collect::<String>()
+}
+
+/// tagsファイルのトラックに指定されたファイル名を、リネーム後のファイル名に書き換える。
+///
+/// tagsファイルがなければ何もしない。コメント行や書き方はそのまま残す。
+/// CUEシートは利用者のファイルなので書き換えない。
+pub fn rename_anchors(tags_filepath: &Path, renames: &[(String, String)]) -> Result<()> {
+    if renames.is_empty() || !tags_filepath.exists() {
+        return Ok(());
+    }
+    if let Some(format) = StructuredFormat::from_filepath(tags_filepath) {
+        return structured_format::rename_anchors(tags_filepath, format, renames);
+    }
+    if cue_sheet::is_cue_sheet(tags_filepath) {
+        return Ok(());
+    }
+
+    let tags_file_contents = read_tags_file(tags_filepath)?;
+
+    let mut changed = false;
+    let mut s = String::with_capacity(tags_file_contents.len());
+    for line in tags_file_contents.split_inclusive('\n') {
+        let content = line.trim_end();
+        let renamed = match ANCHOR_REGEX.captures(content) {
+            Some(captures) if !content.trim_start().starts_with(COMMENT_PREFIX) => {
+                let filename = captures.name("filename").unwrap();
+                renames
+                    .iter()
+                    .find(|(from, _)| from == filename.as_str())
+                    .map(|(_, to)| (filename.start(), filename.end(), to))
+            }
+            _ => None,
+        };
+        match renamed {
+            Some((start, end, to)) => {
+                s.push_str(&line[..start]);
+                s.push_str(to);
+                s.push_str(&line[end..]);
+                changed = true;
+            }
+            None => s.push_str(line),
+        }
+    }
 
-    if let Err(_) = fs::write(tags_filepath, &nfc) {
+    if changed && fs::write(tags_filepath, s).is_err() {
         Err(WriteTagsError::INSTANCE(
             "tagsファイルが書き込めませんでした。",
         ))?
-    };
+    }
 
     Ok(())
 }
 
-/// 画像をファイルに出力する。
-pub fn write_art_work_file(art_work: Option<&Image>) -> Result<()> {
+/// 値があればアルバム情報の「項目名: 値」の行を追加する。
+///
+/// 改行は1行に収まるように空白に置き換える。
+fn push_header(s: &mut String, key: &str, value: Option<&str>) {
+    let value = match value {
+        Some(value) => value.replace(['\r', '\n'], " "),
+        None => return,
+    };
+    if value.trim().is_empty() {
+        return;
+    }
+
+    s.push_str(key);
+    s.push_str(": ");
+    s.push_str(&escape(value.trim(), false));
+    s.push('\n');
+}
+
+/// 画像を指定されたフォルダにファイルとして出力する。
+///
+/// 出力したファイルのパスを返す。
+pub fn write_art_work_file(folder: &Path, art_work: Option<&Image>) -> Result<Option<PathBuf>> {
     let art_work = match art_work {
         Some(art_work) => art_work,
-        None => return Ok(()),
+        None => return Ok(None),
     };
 
-    let art_work_filepath = format!("Folder.{}", art_work.format().extension());
-    let art_work_filepath = Path::new(art_work_filepath.as_str());
+    let art_work_filename = format!("Folder.{}", art_work.format().extension());
+    let art_work_filepath = folder.join(art_work_filename);
 
-    fs::write(art_work_filepath, art_work.data())?;
+    fs::write(art_work_filepath.as_path(), art_work.data())?;
 
-    Ok(())
+    Ok(Some(art_work_filepath))
 }
 
 /// tags読み込みエラー
@@ -182,9 +835,392 @@ pub enum LoadTagsError {
     INSTANCE(&'static str),
 }
 
+/// 行の中の誤り
+///
+/// 誤りの箇所は行の一部分として持ち、行番号とともに誤りの情報にする。
+struct Problem<'a> {
+    part: &'a str,
+    message: &'static str,
+}
+
+type ParseResult<'a, T> = std::result::Result<T, Problem<'a>>;
+
+impl<'a> Problem<'a> {
+    fn new(part: &'a str, message: &'static str) -> Problem<'a> {
+        Problem { part, message }
+    }
+
+    /// 指定された行の誤りの情報にする。
+    fn at(self, line_number: usize, line: &str) -> Diagnostic {
+        Diagnostic::new(line_number, line, self.part, self.message)
+    }
+}
+
+/// tagsファイルの誤りの情報
+///
+/// 列は行頭を1とする文字数で数える。
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    line: usize,
+    column: usize,
+    text: String,
+    message: String,
+}
+
+impl Diagnostic {
+    /// 行と、その行の中の誤りの箇所から誤りの情報を作成する。
+    fn new(line_number: usize, line: &str, part: &str, message: &str) -> Diagnostic {
+        // 誤りの箇所は行の一部分なので、先頭の位置の差が行の中の位置になる
+        let offset = (part.as_ptr() as usize)
+            .saturating_sub(line.as_ptr() as usize)
+            .min(line.len());
+        let column = line
+            .get(..offset)
+            .map_or(0, |prefix| prefix.chars().count())
+            + 1;
+
+        Diagnostic {
+            line: line_number,
+            column,
+            text: line.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    /// 行番号とメッセージ、行の内容、誤りの箇所を指す^を3行で表示する。
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let indent: String = self
+            .text
+            .chars()
+            .take(self.column - 1)
+            .map(|c| match c {
+                '\t' => "\t",
+                c if is_wide(c) => "  ",
+                _ => " ",
+            })
+            .collect();
+
+        write!(
+            f,
+            "{}行目{}文字目: {}\n  {}\n  {}^",
+            self.line, self.column, self.message, self.text, indent
+        )
+    }
+}
+
+/// 端末で2文字分の幅で表示される文字かを判定する。
+fn is_wide(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD)
+}
+
+/// tagsファイル解析エラー
+///
+/// tagsファイルの内容に誤りがある場合に、見つかったすべての誤りをまとめて発生する。
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Error)]
+pub enum ParseTagsError {
+    #[error("tagsファイルに誤りがあります{}", format_diagnostics(.0))]
+    INSTANCE(Vec<Diagnostic>),
+}
+
+fn format_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| format!("\n{}", diagnostic))
+        .collect()
+}
+
 /// tags書き込みエラー
 #[derive(Debug, Error)]
 pub enum WriteTagsError {
     #[error("{0}")]
     INSTANCE(&'static str),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(tags_file_contents: &str) -> AlbumInfo {
+        parse_tags_file(tags_file_contents).unwrap()
+    }
+
+    #[test]
+    fn split_fields_ignores_escaped_separator() {
+        assert_eq!(split_fields("a//b//c"), vec!["a", "b", "c"]);
+        assert_eq!(split_fields(r"a\//b//c"), vec![r"a\//b", "c"]);
+        assert_eq!(split_fields(r"a\\//b"), vec![r"a\\", "b"]);
+        assert_eq!(split_fields(r"a\/"), vec![r"a\/"]);
+    }
+
+    #[test]
+    fn unescape_removes_escape_before_escapable_chars() {
+        assert_eq!(unescape(r"a\/\/b"), "a//b");
+        assert_eq!(unescape(r"\#\*\@\\"), r"#*@\");
+        // エスケープできない文字の前のエスケープ文字はそのまま残す
+        assert_eq!(unescape(r"C:\x"), r"C:\x");
+    }
+
+    #[test]
+    fn escape_round_trips_through_split_and_unescape() {
+        let values = [
+            "a//b",
+            "ends with/",
+            r"ends with\",
+            "*",
+            "**",
+            "# not a comment",
+            "mail @ home",
+            r"\//",
+        ];
+        for value in values {
+            let line = format!("{}//{}", escape(value, true), escape(value, false));
+            let fields = split_fields(&line);
+            assert_eq!(fields.len(), 2, "{}", line);
+            assert_eq!(unescape(fields[0]), value, "{}", line);
+            assert_eq!(unescape(fields[1]), value, "{}", line);
+        }
+    }
+
+    #[test]
+    fn escape_only_escapes_comment_prefix_at_line_start() {
+        assert_eq!(escape("#1", true), r"\#1");
+        assert_eq!(escape("  #1", true), r"  \#1");
+        assert_eq!(escape("#1", false), "#1");
+        assert_eq!(escape("No.#1", true), "No.#1");
+    }
+
+    #[test]
+    fn comment_lines_are_skipped_anywhere() {
+        let album_info = parse(
+            "# comment\nAlbum\n  # indented comment\nArtist\n2020-01-02\n\n# track comment\nTitle 1\n\\#Title 2\n",
+        );
+
+        assert_eq!(album_info.album(), Some("Album"));
+        assert_eq!(album_info.album_artist(), Some("Artist"));
+        let tracks = album_info.discs()[0].tracks();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].title(), Some("Title 1"));
+        assert_eq!(tracks[1].title(), Some("#Title 2"));
+    }
+
+    #[test]
+    fn keep_marker_keeps_current_value() {
+        let album_info = parse("*\nArtist\n*\ngenre: *\n\n*//*\n");
+
+        assert_eq!(album_info.album(), None);
+        assert_eq!(
+            album_info.kept_fields(),
+            &vec![Field::Album, Field::ReleaseDate, Field::Genre]
+        );
+        let track_info = &album_info.discs()[0].tracks()[0];
+        assert_eq!(track_info.title(), None);
+        assert_eq!(
+            track_info.kept_fields(),
+            &vec![Field::Title, Field::Artists]
+        );
+    }
+
+    #[test]
+    fn escaped_keep_marker_is_literal_value() {
+        let album_info = parse("\\*\nArtist\n2020-01-02\n\n\\*//\\*\n");
+
+        assert_eq!(album_info.album(), Some("*"));
+        assert!(album_info.kept_fields().is_empty());
+        let track_info = &album_info.discs()[0].tracks()[0];
+        assert_eq!(track_info.title(), Some("*"));
+        assert_eq!(track_info.artists(), &vec!["*".to_string()]);
+        assert!(track_info.kept_fields().is_empty());
+    }
+
+    #[test]
+    fn keep_marker_cannot_be_mixed_with_artists() {
+        assert!(parse_tags_file("Album\nArtist\n2020-01-02\n\nTitle//*//Artist\n").is_err());
+    }
+
+    #[test]
+    fn formatted_tags_file_round_trips() {
+        let mut album_info = AlbumInfo::new(
+            Some("*".to_string()),
+            Some("#Artist".to_string()),
+            Some("2020-01-02".to_string()),
+        );
+        album_info.set_genre(Some("Rock//Pop".to_string()));
+        album_info.set_comment(Some(r"C:\music\".to_string()));
+        let disc_info = album_info.new_disc();
+        disc_info.set_title(Some("Disc/".to_string()));
+        disc_info.set_artists(vec!["A @ B".to_string()]);
+        disc_info.new_track(Some("*".to_string()), vec!["x//y".to_string()]);
+        disc_info.new_track(Some("01. Not a number".to_string()), vec![]);
+        disc_info.new_track(Some("#hash".to_string()), vec!["\\".to_string()]);
+
+        let parsed = parse(&format_tags_file(&album_info));
+
+        assert_eq!(parsed.album(), Some("*"));
+        assert_eq!(parsed.album_artist(), Some("#Artist"));
+        assert_eq!(parsed.genre(), Some("Rock//Pop"));
+        assert_eq!(parsed.comment(), Some(r"C:\music\"));
+        assert!(parsed.kept_fields().is_empty());
+        let disc_info = &parsed.discs()[0];
+        assert_eq!(disc_info.title(), Some("Disc/"));
+        assert_eq!(disc_info.artists(), &vec!["A @ B".to_string()]);
+        let tracks = disc_info.tracks();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].title(), Some("*"));
+        assert_eq!(tracks[0].artists(), &vec!["x//y".to_string()]);
+        assert!(tracks[0].kept_fields().is_empty());
+        assert_eq!(tracks[1].title(), Some("01. Not a number"));
+        assert_eq!(tracks[2].title(), Some("#hash"));
+        assert_eq!(tracks[2].artists(), &vec!["\\".to_string()]);
+    }
+
+    /// パースの誤りを(行、列、メッセージ)の一覧にする。
+    fn diagnostics(tags_file_contents: &str) -> Vec<(usize, usize, String)> {
+        let e = parse_tags_file(tags_file_contents).unwrap_err();
+        match e.downcast::<ParseTagsError>().unwrap() {
+            ParseTagsError::INSTANCE(diagnostics) => diagnostics
+                .into_iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.column, diagnostic.message))
+                .collect(),
+        }
+    }
+
+    fn diagnostic(line: usize, column: usize, message: &str) -> (usize, usize, String) {
+        (line, column, message.to_string())
+    }
+
+    #[test]
+    fn reports_invalid_release_date() {
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020/01/02\n\nTitle\n"),
+            vec![diagnostic(3, 1, "発売日の形式が不正です。")]
+        );
+    }
+
+    #[test]
+    fn reports_missing_lines_at_end_of_file() {
+        assert_eq!(
+            diagnostics("Album\nArtist\n"),
+            vec![diagnostic(3, 1, "発売日がありません。")]
+        );
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020-01-02\ngenre: Rock\n"),
+            vec![diagnostic(5, 1, "発売日の次の空白行がありませんでした。")]
+        );
+    }
+
+    #[test]
+    fn line_numbers_count_comment_lines() {
+        assert_eq!(
+            diagnostics("# comment\nAlbum\n# comment\nArtist\n2020-1-2\n\nTitle\n"),
+            vec![diagnostic(5, 1, "発売日の形式が不正です。")]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_headers() {
+        assert_eq!(
+            diagnostics(
+                "Album\nArtist\n2020-01-02\nfoo: bar\ncompilation: yes\ngenre:\ngenre: Rock\ngenre: Pop\n\nTitle\n"
+            ),
+            vec![
+                diagnostic(4, 1, "アルバム情報の項目名が不正です。"),
+                diagnostic(5, 14, "compilationにはtrueかfalseを指定してください。"),
+                diagnostic(6, 7, "アルバム情報の値がありません。"),
+                diagnostic(8, 1, "アルバム情報の項目が重複しています。"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_disc_header_problems() {
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020-01-02\n\n== Disc 2: Title ==\nTitle\n\n== Empty ==\n"),
+            vec![
+                diagnostic(
+                    5,
+                    9,
+                    "ディスク情報のディスク番号が何枚目のディスクかと一致しません。"
+                ),
+                diagnostic(8, 1, "ディスクにトラックがありません。"),
+            ]
+        );
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020-01-02\n\n== Title//*//Artist ==\nTitle\n"),
+            vec![diagnostic(
+                5,
+                11,
+                "「*」は他のアーティスト名と一緒に書けません。"
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_anchors_at_filename() {
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020-01-02\n\n曲1 @ 01.wav\n曲2  @ 01.wav\n"),
+            vec![diagnostic(
+                6,
+                7,
+                "同じファイル名が複数のトラックに指定されています。"
+            )]
+        );
+        // 異なるディスクでも同じファイル名は指定できない
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020-01-02\n\nA @ a.wav\n\nB @ a.wav\n"),
+            vec![diagnostic(
+                7,
+                5,
+                "同じファイル名が複数のトラックに指定されています。"
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_explicit_numbering() {
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020-01-02\n\n02. A\nB\n03. C\nA1. D\nA1. E\n"),
+            vec![
+                diagnostic(7, 1, "トラック番号が重複しています。"),
+                diagnostic(9, 1, "トラックの位置が重複しています。"),
+            ]
+        );
+        // 番号はディスクごとに数える
+        assert!(parse_tags_file("Album\nArtist\n2020-01-02\n\n01. A\n\n01. B\n").is_ok());
+    }
+
+    #[test]
+    fn reports_consecutive_blank_lines() {
+        assert_eq!(
+            diagnostics("Album\nArtist\n2020-01-02\n\nA\n\n\nB\n"),
+            vec![diagnostic(7, 1, "空白行が連続しています。")]
+        );
+    }
+
+    #[test]
+    fn diagnostic_display_points_at_column_with_wide_chars() {
+        let line = "曲2 @ 01.wav";
+        let diagnostic = Diagnostic::new(6, line, &line[line.find('0').unwrap()..], "message");
+        assert_eq!(diagnostic.column, 6);
+        assert_eq!(
+            diagnostic.to_string(),
+            "6行目6文字目: message\n  曲2 @ 01.wav\n        ^"
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::audio_file;
+use crate::model::{AlbumInfo, Role, TrackInfo};
+use crate::tags_file::{
+    LoadTagsError, ParseResult, ParseTagsError, Problem, WriteTagsError,
+    ORIGINAL_RELEASE_DATE_REGEX,
+};
+
+/// CUEシートの拡張子
+const CUE_SHEET_EXTENSION: &str = "cue";
+
+/// CUEシートのファイルかを判定する。
+pub fn is_cue_sheet(filepath: &Path) -> bool {
+    filepath
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(CUE_SHEET_EXTENSION))
+}
+
+/// CUEシートを読み込んでアルバム情報を作成する。
+///
+/// FILEに1曲だけが含まれる場合は、そのファイルをトラックに対応付ける。
+/// 拡張子が違っても(WAVEを変換したFLACなど)ファイル名の拡張子以外が同じ音楽ファイルがあれば対応付ける。
+pub fn load(cue_filepath: &Path) -> Result<AlbumInfo> {
+    let contents = match fs::read(cue_filepath) {
+        Ok(contents) => contents,
+        Err(_) => Err(LoadTagsError::INSTANCE("CUEシートが読み込めません"))?,
+    };
+    let contents = decode(&contents).nfc().collect::<String>();
+
+    let folder = cue_filepath.parent().unwrap_or(Path::new("."));
+    let audio_filenames: Vec<String> = audio_file::find_audio_files(folder)?
+        .iter()
+        .map(|audio_file| audio_file.filename().nfc().collect())
+        .collect();
+
+    parse_cue_sheet(&contents, &audio_filenames)
+}
+
+/// CUEシートには出力できないのでエラーにする。
+pub fn write() -> Result<()> {
+    Err(WriteTagsError::INSTANCE(
+        "CUEシートには出力できません。--tagsで出力先を指定してください。",
+    ))?
+}
+
+/// CUEシートの内容を文字列にする。
+///
+/// CUEシートは作成したソフトによって文字コードが異なるので、UTF-8、Shift_JIS、Windows-1252の順に判定する。
+/// Shift_JISはWindows-1252の文字の並びとしても読めることが多いので、かなを含む場合だけ使う。
+fn decode(contents: &[u8]) -> String {
+    let contents = contents.strip_prefix(b"\xef\xbb\xbf").unwrap_or(contents);
+    if let Ok(contents) = std::str::from_utf8(contents) {
+        return contents.to_string();
+    }
+
+    if let Some(decoded) = SHIFT_JIS.decode_without_bom_handling_and_without_replacement(contents) {
+        if decoded
+            .chars()
+            .any(|c| ('\u{3041}'..='\u{30ff}').contains(&c))
+        {
+            return decoded.into_owned();
+        }
+    }
+
+    WINDOWS_1252
+        .decode_without_bom_handling(contents)
+        .0
+        .into_owned()
+}
+
+/// CUEシートの内容をアルバム情報にする。
+///
+/// TRACKより前のTITLE、PERFORMERはアルバム名、アルバムアーティスト名、
+/// TRACKの後のTITLE、PERFORMER、SONGWRITERはタイトル、アーティスト名、作曲者とする。
+/// REM DISCNUMBERでディスクを切り替える。
+fn parse_cue_sheet(contents: &str, audio_filenames: &[String]) -> Result<AlbumInfo> {
+    let mut diagnostics = vec![];
+
+    let mut album_info = AlbumInfo::new(None, None, None);
+    let mut album_artist = None;
+    let mut disc_number = 1;
+    let mut disc_started = false;
+    let mut track_started = false;
+    let mut file = None;
+    // ディスクごとのトラック番号とFILEごとのトラック
+    let mut track_numbers = vec![];
+    let mut file_tracks: Vec<FileTracks> = vec![];
+
+    for (line_number, line) in contents.lines().enumerate() {
+        let line_number = line_number + 1;
+
+        let tokens = match tokenize(line) {
+            Ok(tokens) => tokens,
+            Err(problem) => {
+                diagnostics.push(problem.at(line_number, line));
+                continue;
+            }
+        };
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (command.to_ascii_uppercase(), args),
+            None => continue,
+        };
+
+        let result = match command.as_str() {
+            "REM" => match args {
+                [key, value, ..] if key.eq_ignore_ascii_case("DISCNUMBER") => {
+                    match value.parse::<usize>() {
+                        Ok(number) if number == disc_number && !disc_started => Ok(()),
+                        Ok(number) if number == disc_number + 1 && disc_started => {
+                            disc_number = number;
+                            disc_started = false;
+                            track_started = false;
+                            Ok(())
+                        }
+                        _ => Err(Problem::new(
+                            value,
+                            "ディスク番号が1から順になっていません。",
+                        )),
+                    }
+                }
+                [key, value, ..] if key.eq_ignore_ascii_case("GENRE") && !track_started => {
+                    album_info.set_genre(Some(value.to_string()));
+                    Ok(())
+                }
+                [key, value, ..] if key.eq_ignore_ascii_case("COMMENT") && !track_started => {
+                    album_info.set_comment(Some(value.to_string()));
+                    Ok(())
+                }
+                [key, value, ..] if key.eq_ignore_ascii_case("DATE") && !track_started => {
+                    parse_date(&mut album_info, value)
+                }
+                _ => Ok(()),
+            },
+            "CATALOG" => match args {
+                [catalog, ..] => {
+                    album_info.set_barcode(Some(catalog.to_string()));
+                    Ok(())
+                }
+                [] => Err(Problem::new(line.trim_end(), "値がありません。")),
+            },
+            "FILE" => match args {
+                [filename, ..] => {
+                    file = Some(file_tracks.len());
+                    file_tracks.push(FileTracks {
+                        line_number,
+                        line,
+                        filename,
+                        tracks: vec![],
+                    });
+                    Ok(())
+                }
+                [] => Err(Problem::new(line.trim_end(), "ファイル名がありません。")),
+            },
+            "TRACK" => match args.first().map(|number| (number, number.parse::<usize>())) {
+                Some((number, Ok(track_number))) => {
+                    if !disc_started {
+                        album_info.new_disc();
+                        track_numbers.clear();
+                        disc_started = true;
+                    }
+                    track_started = true;
+                    let disc_info = album_info.discs_mut().last_mut().unwrap();
+                    disc_info
+                        .new_track(None, vec![])
+                        .set_number(Some(track_number));
+                    if let Some(file) = file {
+                        let track_index = disc_info.tracks().len() - 1;
+                        file_tracks[file]
+                            .tracks
+                            .push((disc_number - 1, track_index));
+                    }
+                    if track_numbers.contains(&track_number) {
+                        Err(Problem::new(number, "トラック番号が重複しています。"))
+                    } else {
+                        track_numbers.push(track_number);
+                        Ok(())
+                    }
+                }
+                Some((number, Err(_))) => Err(Problem::new(number, "トラック番号が不正です。")),
+                None => Err(Problem::new(line.trim_end(), "トラック番号がありません。")),
+            },
+            "TITLE" | "PERFORMER" | "SONGWRITER" => match args {
+                [value, ..] => {
+                    let value = value.to_string();
+                    match (
+                        command.as_str(),
+                        current_track(&mut album_info, track_started),
+                    ) {
+                        ("TITLE", Some(track_info)) => track_info.set_title(Some(value)),
+                        ("PERFORMER", Some(track_info)) => track_info.set_artists(vec![value]),
+                        ("SONGWRITER", Some(track_info)) => {
+                            track_info.add_credit(Role::Composer, value)
+                        }
+                        ("TITLE", None) => album_info.set_album(Some(value)),
+                        ("PERFORMER", None) => album_artist = Some(value),
+                        _ => (),
+                    }
+                    Ok(())
+                }
+                [] => Err(Problem::new(line.trim_end(), "値がありません。")),
+            },
+            _ => Ok(()),
+        };
+        if let Err(problem) = result {
+            diagnostics.push(problem.at(line_number, line));
+        }
+    }
+
+    // 1曲だけを含むFILEは音楽ファイルに対応付ける
+    let mut anchored_filenames = vec![];
+    for file_tracks in file_tracks {
+        let FileTracks {
+            line_number,
+            line,
+            filename,
+            tracks,
+        } = file_tracks;
+        if let [(disc_index, track_index)] = tracks.as_slice() {
+            let audio_filename = find_audio_filename(file_name_of(filename), audio_filenames);
+            if let Some(audio_filename) = &audio_filename {
+                if anchored_filenames.contains(audio_filename) {
+                    diagnostics.push(
+                        Problem::new(
+                            filename,
+                            "同じ音楽ファイルが複数のFILEに対応付けられています。",
+                        )
+                        .at(line_number, line),
+                    );
+                    continue;
+                }
+                anchored_filenames.push(audio_filename.clone());
+            }
+            album_info.discs_mut()[*disc_index].tracks_mut()[*track_index]
+                .set_filename(audio_filename);
+        }
+    }
+
+    if !diagnostics.is_empty() {
+        Err(ParseTagsError::INSTANCE(diagnostics))?
+    }
+    if album_info.discs().is_empty() {
+        Err(LoadTagsError::INSTANCE("CUEシートにトラックがありません。"))?
+    }
+
+    // アルバムアーティストと同じアーティスト名はトラックには設定しない
+    if let Some(album_artist) = &album_artist {
+        for disc_info in album_info.discs_mut() {
+            for track_info in disc_info.tracks_mut() {
+                if track_info.artists().len() == 1 && &track_info.artists()[0] == album_artist {
+                    track_info.set_artists(vec![]);
+                }
+            }
+        }
+    }
+    album_info.set_album_artist(album_artist);
+
+    Ok(album_info)
+}
+
+/// FILEとそのFILEに含まれるトラック
+struct FileTracks<'a> {
+    line_number: usize,
+    line: &'a str,
+    filename: &'a str,
+    /// (ディスク、トラック)の位置の一覧
+    tracks: Vec<(usize, usize)>,
+}
+
+/// 現在のディスクの最後のトラックを返す。TRACKより前ならNoneを返す。
+fn current_track(album_info: &mut AlbumInfo, track_started: bool) -> Option<&mut TrackInfo> {
+    if !track_started {
+        return None;
+    }
+    album_info.discs_mut().last_mut()?.tracks_mut().last_mut()
+}
+
+/// REM DATEの日付を発売日にする。
+///
+/// 年だけ、年月だけの場合は発売日にできないので、オリジナル発売日とする。
+fn parse_date<'a>(album_info: &mut AlbumInfo, value: &'a str) -> ParseResult<'a, ()> {
+    let date = value.replace('/', "-");
+    if !ORIGINAL_RELEASE_DATE_REGEX.is_match(&date) {
+        return Err(Problem::new(value, "日付の形式が不正です。"));
+    }
+
+    if date.len() == "yyyy-mm-dd".len() {
+        album_info.set_release_date(Some(date));
+    } else {
+        album_info.set_original_release_date(Some(date));
+    }
+
+    Ok(())
+}
+
+/// FILEのファイル名に対応する音楽ファイルのファイル名を返す。
+///
+/// 同じファイル名がなければ拡張子以外が同じファイル名を探す。
+fn find_audio_filename(filename: &str, audio_filenames: &[String]) -> Option<String> {
+    if audio_filenames
+        .iter()
+        .any(|audio_filename| audio_filename == filename)
+    {
+        return Some(filename.to_string());
+    }
+
+    let stem = Path::new(filename).file_stem()?;
+    audio_filenames
+        .iter()
+        .find(|audio_filename| Path::new(audio_filename).file_stem() == Some(stem))
+        .cloned()
+}
+
+/// フォルダを含むファイル名からファイル名だけを返す。
+fn file_name_of(filename: &str) -> &str {
+    match filename.rfind(['/', '\\']) {
+        Some(i) => &filename[i + 1..],
+        None => filename,
+    }
+}
+
+/// 行を空白区切りの要素に分ける。
+///
+/// ダブルクォートで囲まれた要素は囲みを外した中身を返す。
+fn tokenize(line: &str) -> ParseResult<'_, Vec<&str>> {
+    let mut tokens = vec![];
+
+    let mut rest = line.trim_start_matches('\u{feff}').trim_start();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            match quoted.find('"') {
+                Some(end) => {
+                    tokens.push(&quoted[..end]);
+                    rest = quoted[end + 1..].trim_start();
+                }
+                None => return Err(Problem::new(rest, "引用符が閉じられていません。")),
+            }
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            tokens.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_utf8_with_and_without_bom() {
+        assert_eq!(
+            decode("TITLE \"アルバム\"".as_bytes()),
+            "TITLE \"アルバム\""
+        );
+        assert_eq!(
+            decode(b"\xef\xbb\xbfTITLE \"Caf\xc3\xa9\""),
+            "TITLE \"Café\""
+        );
+    }
+
+    #[test]
+    fn decodes_shift_jis() {
+        let (encoded, _, _) = SHIFT_JIS.encode("TITLE \"さくら 桜\"");
+        assert_eq!(decode(&encoded), "TITLE \"さくら 桜\"");
+    }
+
+    #[test]
+    fn decodes_windows_1252() {
+        // Shift_JISとしても読めるが、かなを含まないのでWindows-1252とする
+        assert_eq!(decode(b"TITLE \"Am\xe9lie\""), "TITLE \"Amélie\"");
+        assert_eq!(decode(b"PERFORMER \"Bj\xf6rk\""), "PERFORMER \"Björk\"");
+    }
+}
@@ -0,0 +1,610 @@
+use std::fmt::{self, Formatter};
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::de::{self, DeserializeSeed, Error as _, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+use crate::model::AlbumInfo;
+use crate::tags::Field;
+use crate::tags_file::{
+    read_tags_file, Diagnostic, ParseTagsError, WriteTagsError, ORIGINAL_RELEASE_DATE_REGEX,
+    RELEASE_DATE_REGEX,
+};
+
+/// アルバム全体で現在の値を残せる項目
+const ALBUM_KEEPABLE_FIELDS: [Field; 10] = [
+    Field::Album,
+    Field::AlbumArtist,
+    Field::ReleaseDate,
+    Field::Genre,
+    Field::Label,
+    Field::CatalogNumber,
+    Field::Barcode,
+    Field::Compilation,
+    Field::Comment,
+    Field::OriginalReleaseDate,
+];
+
+/// ディスクごとに現在の値を残せる項目
+const DISC_KEEPABLE_FIELDS: [Field; 2] = [Field::DiscTitle, Field::Artists];
+
+/// トラックごとに現在の値を残せる項目
+const TRACK_KEEPABLE_FIELDS: [Field; 2] = [Field::Title, Field::Artists];
+
+/// 構造化形式のtagsファイルの形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StructuredFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl StructuredFormat {
+    /// ファイルの拡張子から形式を判定する。
+    /// 構造化形式の拡張子でなければNoneを返す。
+    pub fn from_filepath(filepath: &Path) -> Option<StructuredFormat> {
+        match filepath.extension()?.to_str()? {
+            "json" => Some(StructuredFormat::Json),
+            "toml" => Some(StructuredFormat::Toml),
+            "yaml" | "yml" => Some(StructuredFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// tagsファイルの内容をアルバム情報にする。
+    ///
+    /// 形式の誤りは、テキスト形式と同じく行番号と誤りの箇所を付けたエラーにする。
+    fn parse(&self, contents: &str) -> Result<AlbumInfo> {
+        let result = match self {
+            StructuredFormat::Json => serde_json::from_str(contents).map_err(|e| {
+                let location = format!(" at line {} column {}", e.line(), e.column());
+                let message = e.to_string().trim_end_matches(&location).to_string();
+                (offset_of(contents, e.line(), e.column()), message)
+            }),
+            StructuredFormat::Toml => toml::from_str(contents).map_err(|e| {
+                let offset = e.span().map_or(contents.len(), |span| span.start);
+                (offset, e.message().to_string())
+            }),
+            StructuredFormat::Yaml => {
+                serde_yaml::from_str(contents).map_err(|e| match e.location() {
+                    Some(location) => {
+                        let suffix =
+                            format!(" at line {} column {}", location.line(), location.column());
+                        let message = e.to_string().trim_end_matches(&suffix).to_string();
+                        (location.index(), message)
+                    }
+                    None => (contents.len(), e.to_string()),
+                })
+            }
+        };
+
+        match result {
+            Ok(album_info) => Ok(album_info),
+            Err((offset, message)) => Err(ParseTagsError::INSTANCE(vec![diagnostic_at(
+                contents, offset, &message,
+            )]))?,
+        }
+    }
+
+    /// tagsファイルの内容の中で、指定された場所にある値の先頭の位置を求める。
+    ///
+    /// 値を探しながら読み直し、見つけた値で意図的にエラーを起こして、
+    /// 各形式のエラーに付く位置を使う。場所の項目が省略されていれば、その親の値の位置にする。
+    fn locate(&self, contents: &str, path: &[Segment]) -> usize {
+        for depth in (0..=path.len()).rev() {
+            let seed = Locate {
+                path: &path[..depth],
+            };
+            let offset = match self {
+                StructuredFormat::Json => {
+                    let mut deserializer = serde_json::Deserializer::from_str(contents);
+                    match seed.deserialize(&mut deserializer) {
+                        Err(e) if e.to_string().contains(FOUND) => {
+                            let end = offset_of(contents, e.line(), e.column());
+                            Some(json_value_start(contents, end))
+                        }
+                        _ => None,
+                    }
+                }
+                StructuredFormat::Toml => {
+                    match seed.deserialize(toml::Deserializer::new(contents)) {
+                        Err(e) if e.message().contains(FOUND) => e.span().map(|span| span.start),
+                        _ => None,
+                    }
+                }
+                StructuredFormat::Yaml => {
+                    match seed.deserialize(serde_yaml::Deserializer::from_str(contents)) {
+                        Err(e) if e.to_string().contains(FOUND) => {
+                            e.location().map(|location| location.index())
+                        }
+                        _ => None,
+                    }
+                }
+            };
+            if let Some(offset) = offset {
+                return offset;
+            }
+        }
+
+        0
+    }
+
+    /// アルバム情報をtagsファイルの内容にする。
+    fn serialize(&self, album_info: &AlbumInfo) -> Result<String> {
+        let contents = match self {
+            StructuredFormat::Json => serde_json::to_string_pretty(album_info)? + "\n",
+            StructuredFormat::Toml => toml::to_string_pretty(album_info)?,
+            StructuredFormat::Yaml => serde_yaml::to_string(album_info)?,
+        };
+
+        Ok(contents)
+    }
+}
+
+/// 構造化形式のtagsファイルを読み込んでアルバム情報を作成する。
+///
+/// テキスト形式で書けない値にならないように、テキスト形式と同じ検証をする。
+/// 検証の誤りは、誤りのある値の行番号と位置を付けてまとめてエラーにする。
+pub fn load(tags_filepath: &Path, format: StructuredFormat) -> Result<AlbumInfo> {
+    let contents = read_tags_file(tags_filepath)?;
+
+    let album_info = format.parse(&contents)?;
+
+    let diagnostics: Vec<Diagnostic> = validate(&album_info)
+        .into_iter()
+        .map(|(path, message)| diagnostic_at(&contents, format.locate(&contents, &path), message))
+        .collect();
+    if !diagnostics.is_empty() {
+        Err(ParseTagsError::INSTANCE(diagnostics))?
+    }
+
+    Ok(album_info)
+}
+
+/// 構造化形式のtagsファイルを出力する。
+pub fn write(tags_filepath: &Path, format: StructuredFormat, album_info: &AlbumInfo) -> Result<()> {
+    let contents = format.serialize(album_info)?;
+
+    if fs::write(tags_filepath, contents).is_err() {
+        Err(WriteTagsError::INSTANCE(
+            "tagsファイルが書き込めませんでした。",
+        ))?
+    }
+
+    Ok(())
+}
+
+/// トラックに指定されたファイル名を、リネーム後のファイル名に書き換える。
+pub fn rename_anchors(
+    tags_filepath: &Path,
+    format: StructuredFormat,
+    renames: &[(String, String)],
+) -> Result<()> {
+    let contents = read_tags_file(tags_filepath)?;
+    let mut album_info = format.parse(&contents)?;
+
+    let mut changed = false;
+    for disc_info in album_info.discs_mut() {
+        for track_info in disc_info.tracks_mut() {
+            let renamed = track_info.filename().and_then(|filename| {
+                renames
+                    .iter()
+                    .find(|(from, _)| from == filename)
+                    .map(|(_, to)| to.clone())
+            });
+            if renamed.is_some() {
+                track_info.set_filename(renamed);
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        write(tags_filepath, format, &album_info)?;
+    }
+
+    Ok(())
+}
+
+/// アルバム情報がテキスト形式のtagsファイルで書ける内容かを検証する。
+///
+/// 見つかった誤りを、誤りのある値の場所とメッセージの一覧で返す。
+fn validate(album_info: &AlbumInfo) -> Vec<(Vec<Segment>, &'static str)> {
+    use Segment::{Index, Key};
+
+    let mut problems = vec![];
+
+    if let Some(release_date) = album_info.release_date() {
+        if !RELEASE_DATE_REGEX.is_match(release_date) {
+            problems.push((vec![Key("release_date")], "発売日の形式が不正です。"));
+        }
+    }
+    if let Some(original_release_date) = album_info.original_release_date() {
+        if !ORIGINAL_RELEASE_DATE_REGEX.is_match(original_release_date) {
+            problems.push((
+                vec![Key("original_release_date")],
+                "オリジナル発売日の形式が不正です。",
+            ));
+        }
+    }
+    if let Some(i) = unkeepable_index(album_info.kept_fields(), &ALBUM_KEEPABLE_FIELDS) {
+        problems.push((
+            vec![Key("kept_fields"), Index(i)],
+            "アルバム情報で値を残せない項目が指定されています。",
+        ));
+    }
+
+    if album_info.discs().is_empty() {
+        problems.push((vec![Key("discs")], "ディスクがありません。"));
+    }
+
+    let mut filenames = vec![];
+    for (d, disc_info) in album_info.discs().iter().enumerate() {
+        let disc_path = || vec![Key("discs"), Index(d)];
+        if disc_info.tracks().is_empty() {
+            problems.push((
+                [disc_path(), vec![Key("tracks")]].concat(),
+                "ディスクにトラックがありません。",
+            ));
+        }
+        if let Some(i) = unkeepable_index(disc_info.kept_fields(), &DISC_KEEPABLE_FIELDS) {
+            problems.push((
+                [disc_path(), vec![Key("kept_fields"), Index(i)]].concat(),
+                "ディスク情報で値を残せない項目が指定されています。",
+            ));
+        }
+
+        let mut track_number = 0;
+        let mut numbers = vec![];
+        let mut positions = vec![];
+        for (t, track_info) in disc_info.tracks().iter().enumerate() {
+            let track_path = |key| [disc_path(), vec![Key("tracks"), Index(t), key]].concat();
+            if let Some(i) = unkeepable_index(track_info.kept_fields(), &TRACK_KEEPABLE_FIELDS) {
+                problems.push((
+                    [track_path(Key("kept_fields")), vec![Index(i)]].concat(),
+                    "トラック情報で値を残せない項目が指定されています。",
+                ));
+            }
+
+            // トラック番号が省略されていればトラックの位置にする
+            track_number = track_info.number().unwrap_or(track_number + 1);
+            if numbers.contains(&track_number) {
+                problems.push((track_path(Key("number")), "トラック番号が重複しています。"));
+            }
+            numbers.push(track_number);
+
+            if let Some(position) = track_info.position() {
+                if positions.contains(&position) {
+                    problems.push((
+                        track_path(Key("position")),
+                        "トラックの位置が重複しています。",
+                    ));
+                }
+                positions.push(position);
+            }
+
+            if let Some(filename) = track_info.filename() {
+                if filenames.contains(&filename) {
+                    problems.push((
+                        track_path(Key("filename")),
+                        "同じファイル名が複数のトラックに指定されています。",
+                    ));
+                }
+                filenames.push(filename);
+            }
+        }
+    }
+
+    problems
+}
+
+/// 値を残せない項目があれば、最初のものの位置を返す。
+fn unkeepable_index(kept_fields: &[Field], keepable_fields: &[Field]) -> Option<usize> {
+    kept_fields
+        .iter()
+        .position(|field| !keepable_fields.contains(field))
+}
+
+/// 1から数える行と列から、内容の先頭からのバイト位置を求める。
+fn offset_of(contents: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = contents
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+
+    (line_start + column.saturating_sub(1)).min(contents.len())
+}
+
+/// 内容の先頭からのバイト位置にある誤りの情報を作成する。
+fn diagnostic_at(contents: &str, offset: usize, message: &str) -> Diagnostic {
+    let mut offset = offset.min(contents.len());
+    while !contents.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let line_start = contents[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = contents[line_start..]
+        .find('\n')
+        .map_or(contents.len(), |i| line_start + i);
+    let line = contents[line_start..line_end].trim_end_matches('\r');
+    let line_number = contents[..line_start].matches('\n').count() + 1;
+
+    let part = &line[(offset - line_start).min(line.len())..];
+    Diagnostic::new(line_number, line, part, message)
+}
+
+/// 構造化形式のtagsファイルの中の場所を表す、項目名または配列の位置
+#[derive(Clone, Copy, Debug)]
+enum Segment {
+    Key(&'static str),
+    Index(usize),
+}
+
+/// 値が見つかったことを表すエラーメッセージ
+const FOUND: &str = "utag:found";
+
+/// 指定された場所の値を探し、見つけたらFOUNDのエラーにする。
+///
+/// 場所が空なら、今読んでいる値が探している値になる。
+struct Locate<'a> {
+    path: &'a [Segment],
+}
+
+impl<'de> DeserializeSeed<'de> for Locate<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Locate<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        Err(E::custom(FOUND))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        Err(E::custom(FOUND))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        Err(E::custom(FOUND))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        Err(E::custom(FOUND))
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        Err(E::custom(FOUND))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Err(E::custom(FOUND))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        Err(E::custom(FOUND))
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let (index, rest) = match self.path.split_first() {
+            Some((Segment::Index(index), rest)) => (*index, rest),
+            _ => {
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+                return Err(A::Error::custom(FOUND));
+            }
+        };
+
+        for _ in 0..index {
+            if seq.next_element::<IgnoredAny>()?.is_none() {
+                return Ok(());
+            }
+        }
+        seq.next_element_seed(Locate { path: rest })?;
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let (key, rest) = match self.path.split_first() {
+            Some((Segment::Key(key), rest)) => (*key, rest),
+            _ => {
+                while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                return Err(A::Error::custom(FOUND));
+            }
+        };
+
+        while let Some(other) = map.next_key::<String>()? {
+            if other == key {
+                return map.next_value_seed(Locate { path: rest });
+            }
+            map.next_value::<IgnoredAny>()?;
+        }
+        Ok(())
+    }
+}
+
+/// JSONのエラーの位置(値の最後の文字)から、その値の先頭の位置を求める。
+fn json_value_start(contents: &str, end: usize) -> usize {
+    let bytes = contents.as_bytes();
+    let mut i = end.min(bytes.len().saturating_sub(1));
+    // 値の後の区切りで止まっていれば、その前の値の最後の文字まで戻る
+    while i > 0 && (bytes[i].is_ascii_whitespace() || bytes[i] == b',') {
+        i -= 1;
+    }
+
+    match bytes[i] {
+        b'"' => string_start(bytes, i),
+        b']' | b'}' => {
+            let mut depth = 0;
+            loop {
+                match bytes[i] {
+                    b']' | b'}' => depth += 1,
+                    b'[' | b'{' => depth -= 1,
+                    b'"' => i = string_start(bytes, i),
+                    _ => (),
+                }
+                if depth == 0 || i == 0 {
+                    return i;
+                }
+                i -= 1;
+            }
+        }
+        _ => {
+            while i > 0 && !b" \t\r\n,:[{".contains(&bytes[i - 1]) {
+                i -= 1;
+            }
+            i
+        }
+    }
+}
+
+/// 閉じる"の位置から、対応する開く"の位置を求める。
+fn string_start(bytes: &[u8], end: usize) -> usize {
+    let mut i = end;
+    while i > 0 {
+        i -= 1;
+        if bytes[i] == b'"' {
+            let backslashes = bytes[..i].iter().rev().take_while(|&&b| b == b'\\').count();
+            if backslashes % 2 == 0 {
+                return i;
+            }
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 検証の誤りを(行、列、メッセージ)の一覧にする。
+    fn diagnostics(format: StructuredFormat, contents: &str) -> Vec<(usize, usize, String)> {
+        let album_info = format.parse(contents).unwrap();
+        validate(&album_info)
+            .into_iter()
+            .map(|(path, message)| {
+                let diagnostic = diagnostic_at(contents, format.locate(contents, &path), message);
+                (diagnostic.line, diagnostic.column, diagnostic.message)
+            })
+            .collect()
+    }
+
+    fn diagnostic(line: usize, column: usize, message: &str) -> (usize, usize, String) {
+        (line, column, message.to_string())
+    }
+
+    #[test]
+    fn locates_json_values() {
+        let contents = r#"{
+  "album": "Album",
+  "release_date": "2020/01/02",
+  "discs": [
+    {
+      "tracks": [
+        { "title": "A", "filename": "a.wav" },
+        { "title": "B", "filename": "a.wav" },
+        { "title": "C", "number": 1 }
+      ]
+    },
+    { "tracks": [] }
+  ]
+}
+"#;
+        assert_eq!(
+            diagnostics(StructuredFormat::Json, contents),
+            vec![
+                diagnostic(3, 19, "発売日の形式が不正です。"),
+                diagnostic(8, 37, "同じファイル名が複数のトラックに指定されています。"),
+                diagnostic(9, 35, "トラック番号が重複しています。"),
+                diagnostic(12, 17, "ディスクにトラックがありません。"),
+            ]
+        );
+    }
+
+    #[test]
+    fn locates_omitted_json_value_at_parent() {
+        // 番号が省略されたトラックは、前のトラックの次の番号になる
+        let contents = r#"{"discs": [{"tracks": [{"number": 2}, {"number": 1}, {"title": "B"}]}]}"#;
+        let column = contents.find(r#"{"title""#).unwrap() + 1;
+        assert_eq!(
+            diagnostics(StructuredFormat::Json, contents),
+            vec![diagnostic(1, column, "トラック番号が重複しています。")]
+        );
+        assert_eq!(
+            diagnostics(StructuredFormat::Json, r#"{"album": "A"}"#),
+            vec![diagnostic(1, 1, "ディスクがありません。")]
+        );
+    }
+
+    #[test]
+    fn locates_toml_values() {
+        let contents = r#"album = "Album"
+release_date = "2020/01/02"
+kept_fields = ["title"]
+
+[[discs]]
+[[discs.tracks]]
+title = "A"
+filename = "a.wav"
+
+[[discs.tracks]]
+title = "B"
+filename = "a.wav"
+"#;
+        assert_eq!(
+            diagnostics(StructuredFormat::Toml, contents),
+            vec![
+                diagnostic(2, 16, "発売日の形式が不正です。"),
+                diagnostic(3, 16, "アルバム情報で値を残せない項目が指定されています。"),
+                diagnostic(12, 12, "同じファイル名が複数のトラックに指定されています。"),
+            ]
+        );
+    }
+
+    #[test]
+    fn locates_yaml_values() {
+        let contents = "album: Album
+release_date: 2020/01/02
+discs:
+- tracks:
+  - title: A
+    position: A1
+  - title: B
+    position: A1
+- tracks: []
+";
+        assert_eq!(
+            diagnostics(StructuredFormat::Yaml, contents),
+            vec![
+                diagnostic(2, 15, "発売日の形式が不正です。"),
+                diagnostic(8, 15, "トラックの位置が重複しています。"),
+                diagnostic(9, 11, "ディスクにトラックがありません。"),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_value_start_skips_escaped_quotes_and_nested_values() {
+        let contents = r#"{"a": "x\"y", "b": [1, {"c": "]"}]}"#;
+        let end = contents.find(r#"", "b""#).unwrap();
+        assert_eq!(
+            json_value_start(contents, end),
+            contents.find(r#""x"#).unwrap()
+        );
+        let end = contents.rfind(']').unwrap();
+        assert_eq!(json_value_start(contents, end), contents.find('[').unwrap());
+    }
+}